@@ -0,0 +1,50 @@
+use bursar::{Bursar, ClientMapKind, Op, Transaction};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rust_decimal::Decimal;
+use std::io;
+
+// deposits and withdrawals spread over many clients, with the occasional dispute
+fn workload(clients: u32, transactions: u32) -> Vec<Transaction> {
+    let client_of = |tx_id: u32| (tx_id.wrapping_mul(2_654_435_761) % clients) as u16;
+    (0..transactions)
+        .map(|tx_id| {
+            let client_id = client_of(tx_id);
+            match tx_id % 10 {
+                // disputes the deposit of its own client
+                9 => Transaction::new(Op::Dispute, client_of(tx_id - 9), tx_id - 9, None),
+                3 | 6 => Transaction::new(Op::Withdrawal, client_id, tx_id, Some(Decimal::ONE)),
+                _ => Transaction::new(Op::Deposit, client_id, tx_id, Some(Decimal::TWO)),
+            }
+        })
+        .collect()
+}
+
+fn client_maps(c: &mut Criterion) {
+    let mut group = c.benchmark_group("client_maps");
+    for clients in [1_000u32, 60_000] {
+        for kind in [ClientMapKind::Hash, ClientMapKind::BTree] {
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", kind), clients),
+                &clients,
+                |b, &clients| {
+                    b.iter_batched(
+                        || workload(clients, clients * 4),
+                        |transactions| {
+                            let mut bursar = Bursar::new().with_client_map(kind);
+                            bursar.consume(transactions.into_iter());
+                            // a single shard is the sorted output path
+                            bursar
+                                .write_results_sharded(u32::MAX, |_| Box::new(io::sink()))
+                                .unwrap();
+                        },
+                        criterion::BatchSize::LargeInput,
+                    )
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, client_maps);
+criterion_main!(benches);
@@ -0,0 +1,48 @@
+use bursar::{Bursar, Op, Transaction};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rust_decimal::Decimal;
+
+// rounds of one transaction per client, every tenth round disputes a deposit
+fn workload(clients: u32, transactions: u32) -> Vec<Transaction> {
+    (0..transactions)
+        .map(|tx_id| {
+            let client_id = (tx_id % clients) as u16;
+            match (tx_id / clients) % 10 {
+                9 => Transaction::new(Op::Dispute, client_id, tx_id - 9 * clients, None),
+                3 | 6 => Transaction::new(Op::Withdrawal, client_id, tx_id, Some(Decimal::ONE)),
+                _ => Transaction::new(Op::Deposit, client_id, tx_id, Some(Decimal::TWO)),
+            }
+        })
+        .collect()
+}
+
+fn parallel(c: &mut Criterion) {
+    let mut group = c.benchmark_group("consume_parallel");
+    let clients = 10_000u32;
+    group.bench_function("serial", |b| {
+        b.iter_batched(
+            || workload(clients, clients * 40),
+            |transactions| Bursar::new().consume(transactions.into_iter()),
+            criterion::BatchSize::LargeInput,
+        )
+    });
+    for workers in [2usize, 4, 8] {
+        group.bench_with_input(
+            BenchmarkId::new("parallel", workers),
+            &workers,
+            |b, &workers| {
+                b.iter_batched(
+                    || workload(clients, clients * 40),
+                    |transactions| {
+                        Bursar::new().consume_parallel(transactions.into_iter(), workers)
+                    },
+                    criterion::BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parallel);
+criterion_main!(benches);
@@ -1,16 +1,44 @@
 use csv::WriterBuilder;
-use log::error;
+use log::{error, info, warn};
 use rust_decimal::prelude::*;
 use serde::ser::SerializeStruct;
-use serde::{Deserialize, Serialize, Serializer};
-use std::collections::{HashMap, HashSet};
-use std::io;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::io::BufRead;
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use std::{fmt, fs, io, thread};
 
+#[derive(Clone, Serialize, Deserialize)]
 struct Client {
     client_id: u16,
     available: Decimal,
     held: Decimal,
+    // flagged for review, still part of available
+    pending: Decimal,
     locked: bool,
+    // logical clock value at the moment the client was locked
+    locked_at: Option<u64>,
+    // sum of all deposits, held can never legitimately exceed it
+    deposited: Decimal,
+    withdrawn: Decimal,
+    // available dropped below zero at some point, even if it recovered since
+    went_negative: bool,
+    // number of inflows summed up in `deposited`, an opening balance counts as one
+    deposits: u32,
+    // disputed amounts of open disputes which couldn't be held
+    uncovered: Decimal,
+    disputes: u32,
+    resolves: u32,
+    chargebacks: u32,
+    // logical clock value of the last transaction for the client
+    last_seen: u64,
+    // incremented on every applied transaction
+    version: u64,
 }
 
 impl Client {
@@ -19,7 +47,33 @@ impl Client {
             client_id,
             available: Decimal::default(),
             held: Decimal::default(),
+            pending: Decimal::default(),
             locked: false,
+            locked_at: None,
+            deposited: Decimal::default(),
+            withdrawn: Decimal::default(),
+            went_negative: false,
+            deposits: 0,
+            uncovered: Decimal::default(),
+            disputes: 0,
+            resolves: 0,
+            chargebacks: 0,
+            last_seen: 0,
+            version: 0,
+        }
+    }
+
+    fn snapshot(&self, clock: u64) -> ClientSnapshot {
+        ClientSnapshot {
+            client_id: self.client_id,
+            available: self.available,
+            held: self.held,
+            pending: self.pending,
+            total: self.total(),
+            locked: self.locked,
+            ticks_since_locked: self.locked_at.map(|locked_at| clock - locked_at),
+            version: self.version,
+            went_negative: self.went_negative,
         }
     }
 
@@ -28,241 +82,6770 @@ impl Client {
     }
 
     fn deposit(&mut self, amount: &Decimal) {
-        self.available += amount
+        self.available += amount;
+        self.deposited += amount;
+        self.deposits += 1;
     }
 
     fn withdraw(&mut self, amount: &Decimal) {
-        self.available -= amount
+        self.available -= amount;
+        self.withdrawn += amount;
+        self.track_negative();
+    }
+
+    fn track_negative(&mut self) {
+        if self.available < Decimal::ZERO && !self.went_negative {
+            warn!(
+                "available of client '{:?}' went negative: {}",
+                self.client_id, self.available
+            );
+            self.went_negative = true;
+        }
+    }
+
+    // value moved in and out of the account, unaffected by disputes
+    fn net_flow(&self) -> Decimal {
+        self.deposited - self.withdrawn
+    }
+
+    fn flag(&mut self, amount: &Decimal) {
+        self.pending += amount;
+    }
+
+    fn unflag(&mut self, amount: &Decimal) {
+        self.pending -= amount;
     }
 
     fn dispute(&mut self, amount: &Decimal) {
         self.available -= amount;
         self.held += amount;
+        self.disputes += 1;
+        self.track_negative();
     }
 
     fn resolve(&mut self, amount: &Decimal) {
         self.available += amount;
         self.held -= amount;
+        self.resolves += 1;
     }
 
-    fn chargeback(&mut self, amount: &Decimal) {
+    fn chargeback(&mut self, amount: &Decimal, clock: u64) {
         self.held -= amount;
+        self.chargebacks += 1;
         self.locked = true;
+        self.locked_at.get_or_insert(clock);
+    }
+}
+
+/// Deposit or withdrawal kept around for later disputes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxRecord {
+    // `Op::Deposit` or `Op::Withdrawal`, only deposits can be disputed
+    op: Op,
+    client_id: u16,
+    amount: Decimal,
+    // logical clock value the transaction was processed at
+    seq: u64,
+}
+
+/// Storage of the transactions disputes may reference, see `Bursar::with_tx_store`.
+/// A store may drop records, e.g. to bound its memory, a dispute of a dropped
+/// transaction is rejected with `TxError::UnknownTransaction`.
+pub trait TxStore: Send {
+    fn insert(&mut self, tx_id: u32, record: TxRecord);
+    fn get(&mut self, tx_id: u32) -> Option<TxRecord>;
+    fn contains(&self, tx_id: u32) -> bool;
+    fn remove(&mut self, tx_id: u32) -> Option<TxRecord>;
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// every record kept, in no particular order
+    fn records(&self) -> Box<dyn Iterator<Item = (u32, TxRecord)> + '_>;
+}
+
+/// The default store, keeping every transaction in memory.
+impl TxStore for HashMap<u32, TxRecord> {
+    fn insert(&mut self, tx_id: u32, record: TxRecord) {
+        HashMap::insert(self, tx_id, record);
+    }
+
+    fn get(&mut self, tx_id: u32) -> Option<TxRecord> {
+        HashMap::get(self, &tx_id).copied()
+    }
+
+    fn contains(&self, tx_id: u32) -> bool {
+        self.contains_key(&tx_id)
+    }
+
+    fn remove(&mut self, tx_id: u32) -> Option<TxRecord> {
+        HashMap::remove(self, &tx_id)
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn records(&self) -> Box<dyn Iterator<Item = (u32, TxRecord)> + '_> {
+        Box::new(self.iter().map(|(tx_id, record)| (*tx_id, *record)))
+    }
+}
+
+/// Store keeping at most `capacity` transactions, dropping the least recently
+/// inserted or looked up one to make room.
+pub struct LruTxStore {
+    capacity: usize,
+    // record with the tick it was last used at
+    records: HashMap<u32, (TxRecord, u64)>,
+    // transaction ids by the tick they were last used at
+    recency: BTreeMap<u64, u32>,
+    tick: u64,
+}
+
+impl LruTxStore {
+    /// Panics on a capacity of 0, the store always keeps the latest transaction.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be positive");
+        LruTxStore {
+            capacity,
+            records: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn touch(&mut self, tx_id: u32) -> Option<TxRecord> {
+        let (record, used) = self.records.get_mut(&tx_id)?;
+        self.recency.remove(used);
+        self.tick += 1;
+        *used = self.tick;
+        self.recency.insert(self.tick, tx_id);
+        Some(*record)
+    }
+}
+
+impl TxStore for LruTxStore {
+    fn insert(&mut self, tx_id: u32, record: TxRecord) {
+        if !self.records.contains_key(&tx_id) && self.records.len() >= self.capacity {
+            if let Some((_, evicted)) = self.recency.pop_first() {
+                self.records.remove(&evicted);
+            }
+        }
+        self.records.insert(tx_id, (record, 0));
+        self.touch(tx_id);
+    }
+
+    fn get(&mut self, tx_id: u32) -> Option<TxRecord> {
+        self.touch(tx_id)
+    }
+
+    fn contains(&self, tx_id: u32) -> bool {
+        self.records.contains_key(&tx_id)
+    }
+
+    fn remove(&mut self, tx_id: u32) -> Option<TxRecord> {
+        let (record, used) = self.records.remove(&tx_id)?;
+        self.recency.remove(&used);
+        Some(record)
+    }
+
+    fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    fn records(&self) -> Box<dyn Iterator<Item = (u32, TxRecord)> + '_> {
+        Box::new(
+            self.records
+                .iter()
+                .map(|(tx_id, (record, _))| (*tx_id, *record)),
+        )
+    }
+}
+
+// balance changes caused by the disputes of a transaction
+#[derive(Default, Clone, Serialize, Deserialize)]
+struct DisputeEffect {
+    available: Decimal,
+    held: Decimal,
+    resolved: bool,
+}
+
+impl DisputeEffect {
+    fn record(&mut self, before: (Decimal, Decimal), client: &Client, resolved: bool) {
+        self.available += client.available - before.0;
+        self.held += client.held - before.1;
+        self.resolved = resolved;
+    }
+}
+
+/// Reason `Bursar::verify_dispute_neutral` found a transaction not to be neutral.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotNeutral {
+    /// the last dispute of the transaction hasn't been resolved
+    NotResolved,
+    /// the dispute and resolve didn't cancel out
+    Residual { available: Decimal, held: Decimal },
+}
+
+// open dispute of a transaction
+#[derive(Serialize, Deserialize)]
+struct Dispute {
+    // client whose funds are held
+    client_id: u16,
+    held: Decimal,
+    // part of the disputed amount which wasn't available to be held
+    uncovered: Decimal,
+}
+
+/// Broken consistency rule reported by `Bursar::verify_invariants`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvariantViolation {
+    /// the client holds more than it ever deposited
+    HeldExceedsInflows {
+        client_id: u16,
+        held: Decimal,
+        inflows: Decimal,
+    },
+    /// a dispute was skipped as it would have held more than the client ever deposited
+    DisputeExceedsInflows {
+        client_id: u16,
+        tx_id: u32,
+        held: Decimal,
+        inflows: Decimal,
+    },
+    /// the held funds of all clients differ from the amounts of the open disputes
+    HeldMismatchesDisputed { held: Decimal, disputed: Decimal },
+}
+
+/// Reason a transaction was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxError {
+    /// deposit or withdrawal without an amount
+    MissingAmount,
+    /// deposit or withdrawal of exactly zero
+    ZeroAmount,
+    NegativeAmount,
+    /// deposit or withdrawal reusing the id of an earlier one
+    DuplicateTxId,
+    /// the referenced transaction isn't known
+    UnknownTransaction,
+    /// resolve or chargeback of a transaction which isn't disputed
+    NotDisputed,
+    /// the referenced transaction belongs to another client
+    ForeignTransaction,
+    /// dispute of a transaction whose previous dispute is still open
+    AlreadyDisputed,
+    /// withdrawal exceeding the available funds, or a dispute with
+    /// `BursarConfig::disputes_require_funds`
+    InsufficientFunds,
+    /// resolve or chargeback of more than the client holds
+    InsufficientHeld,
+    /// the dispute would hold more than the client deposited
+    ExceedsInflows,
+    /// partial dispute holding nothing or more than the referenced transaction
+    InvalidDisputeAmount,
+    AlreadyFlagged,
+    NotFlagged,
+    /// the client was finalized in the dispute window mode
+    ClientFinalized,
+    /// the dispute would hold more than `BursarConfig::max_held_ratio` times available
+    ExceedsHeldRatio,
+    /// client missing from `BursarConfig::client_id_map` with
+    /// `BursarConfig::reject_unmapped_clients` set
+    UnmappedClient,
+    /// client unknown with `BursarConfig::require_registered_clients` set
+    UnregisteredClient,
+    /// the ledger was sealed, see `Bursar::seal`
+    Sealed,
+    /// dispute outside of `BursarConfig::dispute_recency`
+    DisputeTooLate,
+    /// the client is locked by a chargeback
+    AccountLocked,
+    /// the dispute references a withdrawal, only deposits can be disputed
+    WithdrawalDispute,
+    /// the flag references a withdrawal, pending funds are only part of deposits
+    WithdrawalFlag,
+    /// failed a rule added by `Bursar::with_rule`
+    RuleViolation,
+    /// more decimal places than `BursarConfig::precision` with
+    /// `BursarConfig::reject_on_precision_loss` set
+    PrecisionLoss,
+    /// chargeback of a transaction which isn't disputed, with
+    /// `BursarConfig::undisputed_chargeback_is_error` set
+    UndisputedChargeback,
+}
+
+impl TxError {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TxError::MissingAmount => "missing_amount",
+            TxError::ZeroAmount => "zero_amount",
+            TxError::NegativeAmount => "negative_amount",
+            TxError::DuplicateTxId => "duplicate_tx_id",
+            TxError::UnknownTransaction => "unknown_transaction",
+            TxError::NotDisputed => "not_disputed",
+            TxError::ForeignTransaction => "foreign_transaction",
+            TxError::AlreadyDisputed => "already_disputed",
+            TxError::InsufficientFunds => "insufficient_funds",
+            TxError::InsufficientHeld => "insufficient_held",
+            TxError::ExceedsInflows => "exceeds_inflows",
+            TxError::InvalidDisputeAmount => "invalid_dispute_amount",
+            TxError::AlreadyFlagged => "already_flagged",
+            TxError::NotFlagged => "not_flagged",
+            TxError::ClientFinalized => "client_finalized",
+            TxError::ExceedsHeldRatio => "exceeds_held_ratio",
+            TxError::UnmappedClient => "unmapped_client",
+            TxError::UnregisteredClient => "unregistered_client",
+            TxError::Sealed => "sealed",
+            TxError::DisputeTooLate => "dispute_too_late",
+            TxError::AccountLocked => "account_locked",
+            TxError::WithdrawalDispute => "withdrawal_dispute",
+            TxError::WithdrawalFlag => "withdrawal_flag",
+            TxError::RuleViolation => "rule_violation",
+            TxError::PrecisionLoss => "precision_loss",
+            TxError::UndisputedChargeback => "undisputed_chargeback",
+        }
+    }
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Reason a `Bursar::dispute_batch` disputed none of its transactions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchDisputeError {
+    UnknownTransaction(u32),
+    /// the transaction belongs to another client
+    ForeignTransaction(u32),
+    /// withdrawals can't be disputed
+    Withdrawal(u32),
+    AlreadyDisputed(u32),
+    /// disputing all transactions would hold more than the client deposited
+    ExceedsInflows,
+    Sealed,
+    /// the client isn't part of `BursarConfig::client_id_map`, which rejects it
+    UnmappedClient,
+    /// the dispute of the transaction got rejected, undoing the ones before it
+    Rejected(u32, TxError),
+}
+
+// state a `Bursar::dispute_batch` may change, to undo a partially applied batch
+struct BatchCheckpoint {
+    client: Option<Client>,
+    flagged: Vec<u32>,
+    dispute_effects: Vec<(u32, DisputeEffect)>,
+    clock: u64,
+    processed: u64,
+    rejected: u64,
+    undisputed_chargebacks: u64,
+    slow_transactions: u64,
+    shadow_rejected: u64,
+    op_counts: HashMap<Op, OpCounts>,
+    touched: bool,
+    // lengths of processed_order, errors, rejects, audit and violations
+    logs: [usize; 5],
+}
+
+/// Aggregate figures over all clients.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BursarStats {
+    /// transactions handed to the engine, including rejected ones
+    pub processed: u64,
+    pub rejected: u64,
+    /// part of `rejected`, in the hard error mode for undisputed chargebacks
+    pub undisputed_chargebacks: u64,
+    /// transactions exceeding `BursarConfig::transaction_budget`
+    pub slow_transactions: u64,
+    /// transactions failing a `RuleMode::Shadow` rule, they are processed anyway
+    pub shadow_rejected: u64,
+    /// clients including the ones removed by `Bursar::tombstone_client`,
+    /// like the figures below
+    pub clients: usize,
+    pub locked: usize,
+    pub available: Decimal,
+    pub held: Decimal,
+}
+
+/// Operation applied to the ledger, recorded with `BursarConfig::audit_log`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub op: Op,
+    pub client_id: u16,
+    pub tx_id: u32,
+    /// amount moved by the operation, for disputes the held amount
+    pub amount: Decimal,
+    /// balances of the client after the operation
+    pub available: Decimal,
+    pub held: Decimal,
+}
+
+/// Point in time copy of a client's balances.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientSnapshot {
+    pub client_id: u16,
+    pub available: Decimal,
+    pub held: Decimal,
+    /// flagged for review but still available
+    pub pending: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    /// number of transactions processed since the client got locked
+    pub ticks_since_locked: Option<u64>,
+    /// number of transactions applied to the client
+    pub version: u64,
+    /// available was below zero at some point, even if it recovered since
+    pub went_negative: bool,
+}
+
+/// Returned by `Bursar::process_if_version` when the client moved on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionMismatch {
+    pub expected: u64,
+    pub actual: u64,
+}
+
+/// Stream position taken by `Bursar::pause`, serializable to keep it together
+/// with `Bursar::save_state`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PausedState {
+    clock: u64,
+    // resolves waiting for their dispute with the clock value they expire at
+    reorder_buffer: VecDeque<(u64, Transaction)>,
+}
+
+impl PausedState {
+    /// number of resolves waiting in the reorder buffer
+    pub fn buffered(&self) -> usize {
+        self.reorder_buffer.len()
+    }
+}
+
+/// Returned by `Bursar::consume_with_error_budget` once too many transactions
+/// got rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorBudgetExceeded {
+    pub processed: u64,
+    pub rejected: u64,
+}
+
+impl fmt::Display for ErrorBudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} transactions rejected",
+            self.rejected, self.processed
+        )
+    }
+}
+
+/// Applied and rejected transactions of one operation type.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpCounts {
+    pub applied: u64,
+    pub rejected: u64,
+}
+
+/// Run report of `Bursar::summary`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Summary {
+    pub deposits: OpCounts,
+    pub withdrawals: OpCounts,
+    pub disputes: OpCounts,
+    pub resolves: OpCounts,
+    pub chargebacks: OpCounts,
+    pub flags: OpCounts,
+    pub unflags: OpCounts,
+    /// rejected transactions by `TxError::as_str`
+    pub rejected_by_reason: BTreeMap<&'static str, u64>,
+    /// distinct clients referenced by a transaction, rejected ones included
+    pub clients: usize,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ops = [
+            ("deposits", self.deposits),
+            ("withdrawals", self.withdrawals),
+            ("disputes", self.disputes),
+            ("resolves", self.resolves),
+            ("chargebacks", self.chargebacks),
+            ("flags", self.flags),
+            ("unflags", self.unflags),
+        ];
+        for (name, counts) in ops {
+            writeln!(
+                f,
+                "{}: {} applied, {} rejected",
+                name, counts.applied, counts.rejected
+            )?;
+        }
+        for (reason, count) in &self.rejected_by_reason {
+            writeln!(f, "rejected as {}: {}", reason, count)?;
+        }
+        write!(f, "clients: {}", self.clients)
+    }
+}
+
+/// Most decimal places `BursarConfig::precision` may ask for, the scale limit of
+/// `Decimal`.
+pub const MAX_PRECISION: u32 = 28;
+
+/// Transactions `Bursar::consume_with_error_budget` processes before the budget
+/// is enforced, so a few early rejects don't abort the run.
+const ERROR_BUDGET_SAMPLE: u64 = 10;
+
+/// Transactions queued per worker of `Bursar::consume_parallel` before the
+/// reading thread waits for it.
+const PARALLEL_QUEUE: usize = 1024;
+
+/// Rounding applied to the monetary output columns.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    /// round half to even
+    #[default]
+    Bankers,
+    /// round half away from zero, `-0.12345` becomes `-0.1235`
+    HalfAwayFromZero,
+}
+
+impl Rounding {
+    fn apply(self, amount: Decimal, dp: u32) -> Decimal {
+        let strategy = match self {
+            Rounding::Bankers => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfAwayFromZero => RoundingStrategy::MidpointAwayFromZero,
+        };
+        amount.round_dp_with_strategy(dp, strategy)
+    }
+}
+
+/// Textual form of the locked output column.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockedRepr {
+    /// `true`/`false`
+    #[default]
+    TrueFalse,
+    /// `1`/`0`
+    OneZero,
+    /// `Y`/`N`
+    YesNo,
+}
+
+impl LockedRepr {
+    fn text(self, locked: bool) -> &'static str {
+        match (self, locked) {
+            (LockedRepr::TrueFalse, true) => "true",
+            (LockedRepr::TrueFalse, false) => "false",
+            (LockedRepr::OneZero, true) => "1",
+            (LockedRepr::OneZero, false) => "0",
+            (LockedRepr::YesNo, true) => "Y",
+            (LockedRepr::YesNo, false) => "N",
+        }
     }
 }
 
-impl Serialize for Client {
+/// Client column of clients removed by `Bursar::tombstone_client`.
+pub const TOMBSTONE_CLIENT: &str = "anonymized";
+
+// output row of a client, formatted according to the config
+struct ClientRow<'a> {
+    client: &'a Client,
+    config: &'a BursarConfig,
+    // written with `TOMBSTONE_CLIENT` instead of its id
+    tombstone: bool,
+}
+
+impl ClientRow<'_> {
+    fn amount(&self, amount: Decimal) -> String {
+        self.config
+            .rounding
+            .apply(amount, self.config.precision())
+            .to_string()
+    }
+
+    fn available(&self) -> Decimal {
+        self.config.snap_to_zero(self.client.available)
+    }
+
+    fn held(&self) -> Decimal {
+        self.config.snap_to_zero(self.client.held)
+    }
+
+    // available, held and total as written
+    fn amounts(&self) -> (String, String, String) {
+        (
+            self.amount(self.available()),
+            self.amount(self.held()),
+            self.amount(self.available() + self.held()),
+        )
+    }
+
+    fn client_column(&self) -> String {
+        match self.tombstone {
+            true => TOMBSTONE_CLIENT.to_string(),
+            false => format!(
+                "{:0width$}",
+                self.client.client_id,
+                width = self.config.client_id_width
+            ),
+        }
+    }
+}
+
+/// Column widths of `Bursar::write_results_fixed_width`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldWidths {
+    pub client: usize,
+    pub available: usize,
+    pub held: usize,
+    pub total: usize,
+    pub locked: usize,
+    /// right-align the numeric columns padded with zeros instead of padding
+    /// them with trailing spaces
+    pub zero_pad: bool,
+}
+
+// pads `value` to `width`, numbers zero-padded after their sign
+fn fixed_width(field: &str, value: &str, width: usize, zero_pad: bool) -> io::Result<String> {
+    if value.len() > width {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} '{}' is {} characters wide, exceeding its width of {}",
+                field,
+                value,
+                value.len(),
+                width
+            ),
+        ));
+    }
+    let padding = width - value.len();
+    Ok(match value.strip_prefix('-') {
+        _ if !zero_pad => format!("{}{}", value, " ".repeat(padding)),
+        Some(digits) => format!("-{}{}", "0".repeat(padding), digits),
+        None => format!("{}{}", "0".repeat(padding), value),
+    })
+}
+
+impl Serialize for ClientRow<'_> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("Client", 4)?;
-        state.serialize_field("client", &self.client_id)?;
-        state.serialize_field("available", &self.available.round_dp(4).to_string())?;
-        state.serialize_field("held", &self.held.round_dp(4).to_string())?;
-        state.serialize_field("total", &self.total().round_dp(4).to_string())?;
-        state.serialize_field("locked", &self.locked)?;
+        let client = self.client;
+        let (available, held, total) = self.amounts();
+        let client_id = self.client_column();
+        let fields = 5 + usize::from(self.config.checksum_column);
+        let mut state = serializer.serialize_struct("Client", fields)?;
+        match self.config.client_id_width {
+            0 if !self.tombstone => state.serialize_field("client", &client.client_id)?,
+            _ => state.serialize_field("client", &client_id)?,
+        }
+        state.serialize_field("available", &available)?;
+        state.serialize_field("held", &held)?;
+        state.serialize_field("total", &total)?;
+        match self.config.locked_repr {
+            LockedRepr::TrueFalse => state.serialize_field("locked", &client.locked)?,
+            repr => state.serialize_field("locked", repr.text(client.locked))?,
+        }
+        if self.config.checksum_column {
+            let checksum = row_checksum(&[&client_id, &available, &held, &total]);
+            state.serialize_field("checksum", &checksum)?;
+        }
         state.end()
     }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "lowercase")]
+/// Checksum of the `checksum` output column over the client, available, held and
+/// total fields as written: the first 8 bytes of their comma joined SHA-256 in hex.
+pub fn row_checksum(fields: &[&str]) -> String {
+    Sha256::digest(fields.join(",").as_bytes())[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Op {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    /// mark a transaction for review without moving any funds
+    Flag,
+    Unflag,
+}
+
+impl Op {
+    fn from_name(name: &str) -> Option<Op> {
+        match name {
+            "deposit" => Some(Op::Deposit),
+            "withdrawal" => Some(Op::Withdrawal),
+            "dispute" => Some(Op::Dispute),
+            "resolve" => Some(Op::Resolve),
+            "chargeback" => Some(Op::Chargeback),
+            "flag" => Some(Op::Flag),
+            "unflag" => Some(Op::Unflag),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Op::Deposit => "deposit",
+            Op::Withdrawal => "withdrawal",
+            Op::Dispute => "dispute",
+            Op::Resolve => "resolve",
+            Op::Chargeback => "chargeback",
+            Op::Flag => "flag",
+            Op::Unflag => "unflag",
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
+impl Serialize for Op {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Op {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw = String::deserialize(deserializer)?;
+        match Op::from_name(&raw) {
+            Some(op) => Ok(op),
+            None if raw.trim().is_empty() => {
+                Err(serde::de::Error::custom("missing operation type"))
+            }
+            None => Err(serde::de::Error::custom(format!(
+                "unknown operation type '{}'",
+                raw
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Transaction {
-    #[serde(alias = "type")]
+    #[serde(alias = "type", alias = "transaction_type")]
     tx_type: Op,
     #[serde(alias = "client")]
     client_id: u16,
-    #[serde(alias = "tx")]
+    #[serde(alias = "tx", alias = "transaction", alias = "transaction_id")]
     tx_id: u32,
     #[serde(with = "rust_decimal::serde::arbitrary_precision_option")]
     amount: Option<Decimal>,
+    // share of the referenced transaction a dispute holds, in percent
+    #[serde(default, with = "rust_decimal::serde::arbitrary_precision_option")]
+    percent: Option<Decimal>,
+    // position in an external stream, e.g. a message queue offset
+    #[serde(default)]
+    seq: Option<u64>,
+    // time the transaction happened at, see `Bursar::consume_ordered`
+    #[serde(default)]
+    timestamp: Option<u64>,
+    // client id before `BursarConfig::client_id_map`, restored for the reject log
+    #[serde(skip)]
+    input_client_id: Option<u16>,
 }
 
-impl Transaction {
-    pub fn new(tx_type: Op, client_id: u16, tx_id: u32, amount: Option<Decimal>) -> Self {
-        Transaction {
-            tx_type,
-            client_id,
-            tx_id,
-            amount,
+/// Canonical transaction columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Field {
+    Type,
+    Client,
+    Tx,
+    Amount,
+}
+
+impl Field {
+    fn header(self) -> &'static str {
+        match self {
+            Field::Type => "type",
+            Field::Client => "client",
+            Field::Tx => "tx",
+            Field::Amount => "amount",
         }
     }
 }
 
-pub struct Bursar {
-    transactions: HashMap<u32, Option<Decimal>>,
-    clients: HashMap<u16, Client>,
-    disputed: HashSet<u32>,
-}
+/// Source read by the csv reader of `transaction_reader`, the first line read
+/// ahead to tell whether it is a header followed by the rest.
+pub type TransactionSource<R> = io::Chain<io::Cursor<Vec<u8>>, io::BufReader<R>>;
 
-impl Bursar {
-    pub fn new() -> Self {
-        Bursar {
-            transactions: HashMap::new(),
-            clients: HashMap::new(),
-            disputed: HashSet::new(),
-        }
-    }
+/// Creates a csv reader for transactions, renaming the source headers onto the
+/// canonical fields through `header_map`. Header names are matched case-insensitively,
+/// headers missing from the map are kept as they are. Whitespace around fields is
+/// trimmed. A source starting with a transaction instead of a header row is read
+/// as headerless, with the columns in the order type, client, tx, amount.
+pub fn transaction_reader<R: io::Read>(
+    source: R,
+    header_map: &HashMap<String, Field>,
+) -> csv::Result<csv::Reader<TransactionSource<R>>> {
+    let header_map: HashMap<String, Field> = header_map
+        .iter()
+        .map(|(name, field)| (name.to_lowercase(), *field))
+        .collect();
+    // a single read may return less than the first field, so the whole first
+    // line is read ahead and handed to the csv reader in front of the rest
+    let mut source = io::BufReader::new(source);
+    let mut first_line = Vec::new();
+    source.read_until(b'\n', &mut first_line)?;
+    let first_field = first_line
+        .iter()
+        .position(|byte| matches!(byte, b',' | b'\r' | b'\n'))
+        .map_or(&first_line[..], |end| &first_line[..end]);
+    let headerless = Op::from_name(String::from_utf8_lossy(first_field).trim()).is_some();
 
-    pub fn consume(&mut self, transactions: impl Iterator<Item = Transaction>) {
-        transactions.for_each(|tx| self.process_transaction(tx));
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .has_headers(!headerless)
+        .from_reader(io::Read::chain(io::Cursor::new(first_line), source));
+    if headerless {
+        return Ok(reader);
     }
+    let headers: csv::StringRecord = reader
+        .headers()?
+        .iter()
+        .map(|name| {
+            let name = name.to_lowercase();
+            match header_map.get(&name) {
+                Some(field) => field.header().to_string(),
+                None => name,
+            }
+        })
+        .collect();
+    reader.set_headers(headers);
+    Ok(reader)
+}
 
-    pub fn process_transaction(&mut self, tx: Transaction) {
-        let client = self
-            .clients
-            .entry(tx.client_id)
-            .or_insert_with(|| Client::new(tx.client_id));
+/// Deserializes the transactions of `reader`, records which can't be read are logged
+/// and skipped so the ones after them are still processed.
+pub fn read_transactions<R: io::Read>(
+    reader: &mut csv::Reader<R>,
+) -> impl Iterator<Item = Transaction> + '_ {
+    reader
+        .deserialize::<Transaction>()
+        .filter_map(|item| match item {
+            Ok(tx) => Some(tx),
+            Err(err) => {
+                match err.kind() {
+                    csv::ErrorKind::Utf8 { pos, err } => error!(
+                        "invalid utf-8 in field {} at byte {}, record will be skipped",
+                        err.field() + 1,
+                        pos.as_ref().map_or(0, |pos| pos.byte()),
+                    ),
+                    _ => error!("could not parse transaction, will be skipped: {:?}", err),
+                }
+                None
+            }
+        })
+}
 
-        let amount = match tx.tx_type {
-            Op::Deposit | Op::Withdrawal => {
-                // keep amount of transaction that might be referenced to
-                self.transactions.entry(tx.tx_id).or_insert(tx.amount);
-                &tx.amount
+/// Dispute sequencing problem found by `analyze_disputes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeIssue {
+    /// the dispute references a transaction which wasn't seen before
+    UnknownTransaction,
+    DoubleDispute,
+    ResolveWithoutDispute,
+    ChargebackWithoutDispute,
+    /// dispute, resolve or chargeback following a chargeback
+    AfterChargeback,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisputeProblem {
+    /// line of the offending record in the source
+    pub line: u64,
+    pub tx_id: u32,
+    pub issue: DisputeIssue,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DisputeAnalysis {
+    pub problems: Vec<DisputeProblem>,
+}
+
+/// Checks the dispute sequencing of a transaction stream up front without
+/// processing it. Balances aren't looked at, records which can't be parsed are
+/// skipped.
+pub fn analyze_disputes<R: io::Read>(source: R) -> csv::Result<DisputeAnalysis> {
+    #[derive(PartialEq)]
+    enum State {
+        Undisputed,
+        Disputed,
+        ChargedBack,
+    }
+
+    let mut reader = transaction_reader(source, &HashMap::new())?;
+    let headers = match reader.has_headers() {
+        true => Some(reader.headers()?.clone()),
+        false => None,
+    };
+    let mut states: HashMap<u32, State> = HashMap::new();
+    let mut analysis = DisputeAnalysis::default();
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                error!("could not read record, will be skipped: {}", err);
+                continue;
             }
-            Op::Dispute => {
-                self.disputed.insert(tx.tx_id);
-                self.transactions.get(&tx.tx_id).unwrap_or(&None)
+        };
+        let line = record.position().map_or(0, |pos| pos.line());
+        let tx: Transaction = match record.deserialize(headers.as_ref()) {
+            Ok(tx) => tx,
+            Err(err) => {
+                error!("could not parse transaction on line {}: {}", line, err);
+                continue;
             }
-            Op::Resolve | Op::Chargeback => {
-                if self.disputed.contains(&tx.tx_id) {
-                    // retrieve amount associated to referenced transaction
-                    self.transactions.get(&tx.tx_id).unwrap_or(&None)
-                } else {
-                    // the resolve or chargeback is referencing a undisputed transaction
-                    &None
+        };
+        let state = states.get(&tx.tx_id);
+        let issue = match tx.tx_type {
+            Op::Deposit | Op::Withdrawal => {
+                states.entry(tx.tx_id).or_insert(State::Undisputed);
+                None
+            }
+            Op::Flag | Op::Unflag => None,
+            _ if state == Some(&State::ChargedBack) => Some(DisputeIssue::AfterChargeback),
+            Op::Dispute => match state {
+                None => Some(DisputeIssue::UnknownTransaction),
+                Some(State::Disputed) => Some(DisputeIssue::DoubleDispute),
+                Some(_) => {
+                    states.insert(tx.tx_id, State::Disputed);
+                    None
                 }
+            },
+            Op::Resolve if state != Some(&State::Disputed) => {
+                Some(DisputeIssue::ResolveWithoutDispute)
+            }
+            Op::Chargeback if state != Some(&State::Disputed) => {
+                Some(DisputeIssue::ChargebackWithoutDispute)
+            }
+            Op::Resolve => {
+                states.insert(tx.tx_id, State::Undisputed);
+                None
+            }
+            Op::Chargeback => {
+                states.insert(tx.tx_id, State::ChargedBack);
+                None
             }
         };
-        if let Some(amount) = amount {
-            match tx.tx_type {
-                Op::Deposit => client.deposit(amount),
-                Op::Withdrawal => client.withdraw(amount),
-                Op::Dispute => client.dispute(amount),
-                Op::Resolve => client.resolve(amount),
-                Op::Chargeback => client.chargeback(amount),
+        if let Some(issue) = issue {
+            analysis.problems.push(DisputeProblem {
+                line,
+                tx_id: tx.tx_id,
+                issue,
+            });
+        }
+    }
+    Ok(analysis)
+}
+
+/// Compares two result csvs by their content: rows may come in any order and
+/// amounts may differ by up to 0.0001, e.g. through formatting or rounding. Clients
+/// may have any of the `LockedRepr` forms. Client ids compare by value, so zero
+/// padded ones match, while other client columns like `TOMBSTONE_CLIENT` compare
+/// as text and may repeat. Unreadable input never compares equal.
+pub fn csv_semantically_equal<A: io::Read, B: io::Read>(a: A, b: B) -> bool {
+    type Row = (Decimal, Decimal, Decimal, bool);
+
+    fn parse<R: io::Read>(source: R) -> Option<HashMap<String, Vec<Row>>> {
+        let mut rows: HashMap<String, Vec<Row>> = HashMap::new();
+        for record in csv::Reader::from_reader(source).records() {
+            let record = record.ok()?;
+            let amount = |index: usize| record.get(index)?.trim().parse::<Decimal>().ok();
+            let locked = match record.get(4)?.trim() {
+                "true" | "1" | "Y" => true,
+                "false" | "0" | "N" => false,
+                _ => return None,
+            };
+            let client = record.get(0)?.trim();
+            let client_id = client.parse::<u16>().ok();
+            let key = client_id.map_or_else(|| client.to_string(), |id| id.to_string());
+            let row = (amount(1)?, amount(2)?, amount(3)?, locked);
+            let client_rows = rows.entry(key).or_default();
+            if client_id.is_some() && !client_rows.is_empty() {
+                return None;
             }
-        } else {
-            error!("transactions '{:?}' is not valid", tx.tx_id);
+            client_rows.push(row);
         }
+        rows.values_mut().for_each(|client_rows| client_rows.sort());
+        Some(rows)
     }
 
-    pub fn write_results<T: io::Write>(&mut self, target: T) {
-        let mut writer = WriterBuilder::new().from_writer(target);
-        self.clients.values().for_each(|client| {
-            writer
-                .serialize(client)
-                .expect("Unable to serialize client");
-        });
-        writer.flush().expect("Unable to write to target");
+    let tolerance = Decimal::new(1, 4);
+    let close = |x: Decimal, y: Decimal| (x - y).abs() <= tolerance;
+    match (parse(a), parse(b)) {
+        (Some(a), Some(b)) => {
+            a.len() == b.len()
+                && a.iter().all(|(client, xs)| {
+                    b.get(client).is_some_and(|ys| {
+                        xs.len() == ys.len()
+                            && xs.iter().zip(ys).all(|(x, y)| {
+                                close(x.0, y.0) && close(x.1, y.1) && close(x.2, y.2) && x.3 == y.3
+                            })
+                    })
+                })
+        }
+        _ => false,
     }
 }
 
-#[cfg(test)]
-use rust_decimal_macros::dec;
-
-#[test]
-fn sanity() {
+/// Processes `txs` on a fresh `Bursar`, returning the final snapshots ordered by
+/// client id.
+pub fn run(txs: Vec<Transaction>) -> Vec<ClientSnapshot> {
     let mut bursar = Bursar::new();
-    let client_id = 1;
+    bursar.consume(txs.into_iter());
+    bursar
+        .clients
+        .sorted()
+        .into_iter()
+        .map(|client| client.snapshot(bursar.clock))
+        .collect()
+}
 
-    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 1, Some(dec!(20))));
-    bursar.process_transaction(Transaction::new(
-        Op::Withdrawal,
-        client_id,
-        2,
-        Some(dec!(10)),
-    ));
+/// SHA-256 Merkle root over `leaves` in the given order. Leaves and inner nodes are
+/// hashed with distinct prefixes, a node without sibling is carried up unchanged.
+pub fn merkle_root<L: AsRef<[u8]>>(leaves: &[L]) -> [u8; 32] {
+    let mut level: Vec<[u8; 32]> = leaves
+        .iter()
+        .map(|leaf| {
+            Sha256::new()
+                .chain_update([0])
+                .chain_update(leaf)
+                .finalize()
+                .into()
+        })
+        .collect();
+    if level.is_empty() {
+        return Sha256::digest([]).into();
+    }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => Sha256::new()
+                    .chain_update([1])
+                    .chain_update(left)
+                    .chain_update(right)
+                    .finalize()
+                    .into(),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
 
-    let client = bursar.clients.get(&client_id);
-    assert!(client.is_some());
-    let client = client.unwrap();
-    assert_eq!(client.total(), dec!(10));
-    assert_eq!(client.available, dec!(10));
-    assert_eq!(client.held, dec!(0));
-    assert_eq!(client.locked, false);
+/// Checks exported rows, as returned by `Bursar::commitment_leaves`, against a
+/// `Bursar::state_commitment`.
+pub fn verify_commitment<L: AsRef<[u8]>>(leaves: &[L], root: &[u8; 32]) -> bool {
+    merkle_root(leaves) == *root
 }
 
-#[test]
-fn basic_dispute() {
-    let mut bursar = Bursar::new();
-    let client_id = 1;
+/// Amount parser for accounting formatted sources where `(10.00)` means `-10.00`.
+/// Opt in with `#[serde(deserialize_with = "bursar::accounting_amount")]`.
+pub fn accounting_amount<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw = match Option::<String>::deserialize(deserializer)? {
+        Some(raw) if !raw.trim().is_empty() => raw,
+        _ => return Ok(None),
+    };
+    let raw = raw.trim();
+    let (digits, negative) = match raw.strip_prefix('(').and_then(|r| r.strip_suffix(')')) {
+        Some(inner) => (inner.trim(), true),
+        None => (raw, false),
+    };
+    let amount = Decimal::from_str(digits).map_err(serde::de::Error::custom)?;
+    Ok(Some(if negative { -amount } else { amount }))
+}
 
-    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 1, Some(dec!(10))));
+impl Transaction {
+    pub fn new(tx_type: Op, client_id: u16, tx_id: u32, amount: Option<Decimal>) -> Self {
+        Transaction {
+            tx_type,
+            client_id,
+            tx_id,
+            amount,
+            percent: None,
+            seq: None,
+            timestamp: None,
+            input_client_id: None,
+        }
+    }
+
+    /// Tags the transaction with its position in an external stream, see
+    /// `Bursar::last_processed_seq`.
+    pub fn with_seq(mut self, seq: u64) -> Self {
+        self.seq = Some(seq);
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Dispute of `percent` percent of the referenced transaction.
+    pub fn with_percent(mut self, percent: Decimal) -> Self {
+        self.percent = Some(percent);
+        self
+    }
+
+    pub fn op(&self) -> &Op {
+        &self.tx_type
+    }
+
+    pub fn client_id(&self) -> u16 {
+        self.client_id
+    }
+
+    pub fn tx_id(&self) -> u32 {
+        self.tx_id
+    }
+
+    pub fn amount(&self) -> Option<Decimal> {
+        self.amount
+    }
+
+    pub fn percent(&self) -> Option<Decimal> {
+        self.percent
+    }
+
+    pub fn seq(&self) -> Option<u64> {
+        self.seq
+    }
+
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+}
+
+/// What to do with a client whose total turned negative after a chargeback.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NegativeTotalPolicy {
+    /// keep the negative total to track the debt
+    #[default]
+    Allow,
+    /// write off the shortfall, bringing the total back to zero
+    ClampZero,
+}
+
+/// What to do with deposits to a client locked by a chargeback.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockedDepositPolicy {
+    /// reject them with `TxError::AccountLocked` like any other transaction
+    #[default]
+    Reject,
+    /// hold them back and apply them once the client is unlocked
+    Queue,
+}
+
+/// How a rule added by `Bursar::with_rule` treats transactions failing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleMode {
+    /// reject them with `TxError::RuleViolation`
+    Enforce,
+    /// only log and count them in `BursarStats::shadow_rejected`, to measure a
+    /// rule before enforcing it
+    Shadow,
+}
+
+// check run on every transaction before it is applied
+struct ValidationRule {
+    name: String,
+    mode: RuleMode,
+    check: Box<dyn Fn(&Transaction) -> bool + Send>,
+}
+
+/// Map type used to store the clients.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientMapKind {
+    #[default]
+    Hash,
+    /// keeps clients ordered by id, sorted output comes for free
+    BTree,
+}
+
+enum Clients {
+    Hash(HashMap<u16, Client>),
+    BTree(BTreeMap<u16, Client>),
+}
+
+impl Clients {
+    fn new(kind: ClientMapKind) -> Self {
+        match kind {
+            ClientMapKind::Hash => Clients::Hash(HashMap::new()),
+            ClientMapKind::BTree => Clients::BTree(BTreeMap::new()),
+        }
+    }
+
+    fn get(&self, client_id: &u16) -> Option<&Client> {
+        match self {
+            Clients::Hash(clients) => clients.get(client_id),
+            Clients::BTree(clients) => clients.get(client_id),
+        }
+    }
+
+    fn get_mut(&mut self, client_id: &u16) -> Option<&mut Client> {
+        match self {
+            Clients::Hash(clients) => clients.get_mut(client_id),
+            Clients::BTree(clients) => clients.get_mut(client_id),
+        }
+    }
+
+    fn get_or_create(&mut self, client_id: u16) -> &mut Client {
+        match self {
+            Clients::Hash(clients) => clients
+                .entry(client_id)
+                .or_insert_with(|| Client::new(client_id)),
+            Clients::BTree(clients) => clients
+                .entry(client_id)
+                .or_insert_with(|| Client::new(client_id)),
+        }
+    }
+
+    fn insert(&mut self, client: Client) {
+        match self {
+            Clients::Hash(clients) => clients.insert(client.client_id, client),
+            Clients::BTree(clients) => clients.insert(client.client_id, client),
+        };
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Clients::Hash(clients) => clients.len(),
+            Clients::BTree(clients) => clients.len(),
+        }
+    }
+
+    fn values(&self) -> Box<dyn Iterator<Item = &Client> + '_> {
+        match self {
+            Clients::Hash(clients) => Box::new(clients.values()),
+            Clients::BTree(clients) => Box::new(clients.values()),
+        }
+    }
+
+    fn remove(&mut self, client_id: &u16) -> Option<Client> {
+        match self {
+            Clients::Hash(clients) => clients.remove(client_id),
+            Clients::BTree(clients) => clients.remove(client_id),
+        }
+    }
+
+    fn into_values(self) -> Box<dyn Iterator<Item = Client>> {
+        match self {
+            Clients::Hash(clients) => Box::new(clients.into_values()),
+            Clients::BTree(clients) => Box::new(clients.into_values()),
+        }
+    }
+
+    // clients ordered by id, only the hash map needs sorting
+    fn sorted(&self) -> Vec<&Client> {
+        let mut clients: Vec<&Client> = self.values().collect();
+        if let Clients::Hash(_) = self {
+            clients.sort_unstable_by_key(|client| client.client_id);
+        }
+        clients
+    }
+}
+
+/// Optional behaviour of a `Bursar`, everything is off by default.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BursarConfig {
+    /// log the count and ids of locked clients at the end of `consume`
+    pub log_locked_summary: bool,
+    /// number of transactions `consume` waits for the dispute of a resolve
+    /// referencing an undisputed transaction, 0 disables reordering
+    pub reorder_window: usize,
+    pub negative_total_policy: NegativeTotalPolicy,
+    pub locked_deposit_policy: LockedDepositPolicy,
+    pub client_map: ClientMapKind,
+    pub rounding: Rounding,
+    /// decimal places of the monetary output columns, 4 unless set, at most
+    /// `MAX_PRECISION`
+    pub precision: Option<u32>,
+    /// round the balances to `precision` after every transaction instead of
+    /// only in the output, a dispute and its resolve may then not cancel out
+    pub round_stored_balances: bool,
+    /// reject deposits and withdrawals with more decimal places than `precision`
+    /// instead of rounding them in the output
+    pub reject_on_precision_loss: bool,
+    pub locked_repr: LockedRepr,
+    /// number of transactions a deposit or withdrawal stays disputable for,
+    /// 0 keeps them forever
+    pub dispute_window: usize,
+    /// keep rejected transactions around, see `Bursar::rejects`
+    pub track_rejects: bool,
+    /// client ids are replaced through this table before processing, e.g. to
+    /// pseudonymize the output
+    pub client_id_map: HashMap<u16, u16>,
+    /// reject transactions of clients missing from `client_id_map` instead of
+    /// keeping their id
+    pub reject_unmapped_clients: bool,
+    /// reject transactions of clients which weren't added through
+    /// `Bursar::register_client` instead of creating them
+    pub require_registered_clients: bool,
+    /// reject disputes which would drive available below zero
+    pub disputes_require_funds: bool,
+    /// reject disputes processed more than this many transactions after the
+    /// disputed one
+    pub dispute_recency: Option<u32>,
+    /// reject disputes which would hold more than this multiple of the available
+    /// balance before the dispute
+    pub max_held_ratio: Option<Decimal>,
+    /// disputes hold at most the available funds, the remainder is tracked as
+    /// uncovered instead of driving available negative, see `Bursar::uncovered`
+    pub cap_uncovered_disputes: bool,
+    /// withdraw whatever is available instead of rejecting withdrawals exceeding it
+    pub partial_withdrawals: bool,
+    /// report transactions taking longer than this to apply, they can't be
+    /// interrupted but are logged and counted in `BursarStats::slow_transactions`
+    pub transaction_budget: Option<Duration>,
+    /// record every applied operation, see `Bursar::audit_log`
+    pub audit_log: bool,
+    /// record the id of every processed transaction, see `Bursar::processed_order`
+    pub track_processing_order: bool,
+    /// chargebacks of undisputed transactions point at corrupt input, classify
+    /// them as `TxError::UndisputedChargeback` instead of `TxError::NotDisputed`
+    pub undisputed_chargeback_is_error: bool,
+    /// capacity of a `BufWriter` put in front of the `write_results` target, by
+    /// default the target is written to directly
+    pub output_buffer: Option<usize>,
+    /// zero-pad the client column to this width, 0 leaves the ids unpadded
+    pub client_id_width: usize,
+    /// add a `checksum` column to the output, see `row_checksum`
+    pub checksum_column: bool,
+    /// balances closer to zero than this are output as zero
+    pub zero_epsilon: Decimal,
+    /// leave clients without any balance out of the output, locked ones are kept
+    pub skip_zero_balances: bool,
+}
+
+impl BursarConfig {
+    /// Loads the options from a JSON file, options which aren't given keep their
+    /// defaults while unknown ones and a `precision` above `MAX_PRECISION` are
+    /// an error.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = fs::File::open(path)?;
+        let config: BursarConfig = serde_json::from_reader(io::BufReader::new(file))?;
+        match config.precision {
+            Some(precision) if precision > MAX_PRECISION => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "precision {} exceeds the maximum of {}",
+                    precision, MAX_PRECISION
+                ),
+            )),
+            _ => Ok(config),
+        }
+    }
+
+    fn precision(&self) -> u32 {
+        self.precision.unwrap_or(4)
+    }
+
+    fn round_stored(&self, client: &mut Client) {
+        if self.round_stored_balances {
+            client.available = self.rounding.apply(client.available, self.precision());
+            client.held = self.rounding.apply(client.held, self.precision());
+        }
+    }
+
+    fn snap_to_zero(&self, amount: Decimal) -> Decimal {
+        if amount.abs() < self.zero_epsilon {
+            Decimal::ZERO
+        } else {
+            amount
+        }
+    }
+
+    fn reported(&self, client: &Client) -> bool {
+        !self.skip_zero_balances
+            || client.locked
+            || !self.snap_to_zero(client.available).is_zero()
+            || !self.snap_to_zero(client.held).is_zero()
+    }
+}
+
+pub struct Bursar {
+    transactions: Box<dyn TxStore>,
+    clients: Clients,
+    // open disputes with the amount held for them, closed by a resolve or chargeback
+    disputed: HashMap<u32, Dispute>,
+    flagged: HashSet<u32>,
+    config: BursarConfig,
+    // logical clock, incremented for every processed transaction
+    clock: u64,
+    violations: Vec<InvariantViolation>,
+    // resolves waiting for their dispute, with the clock value they expire at
+    reorder_buffer: VecDeque<(u64, Transaction)>,
+    // processed transactions inside the dispute window as (clock, client, tx)
+    window: VecDeque<(u64, u16, u32)>,
+    finalized: HashSet<u16>,
+    on_finalized: Option<Box<dyn FnMut(ClientSnapshot) + Send>>,
+    rules: Vec<ValidationRule>,
+    // hash of the row last written by `write_results_incremental` per client
+    emitted_rows: HashMap<u16, u64>,
+    dispute_effects: HashMap<u32, DisputeEffect>,
+    rejects: Vec<(Transaction, TxError)>,
+    processed: u64,
+    rejected: u64,
+    undisputed_chargebacks: u64,
+    slow_transactions: u64,
+    shadow_rejected: u64,
+    op_counts: HashMap<Op, OpCounts>,
+    // clients referenced by any transaction, for `summary`
+    touched: HashSet<u16>,
+    processed_order: Vec<u32>,
+    sealed: bool,
+    queued_deposits: HashMap<u16, Vec<Transaction>>,
+    audit: Vec<AuditEntry>,
+    errors: Vec<TxError>,
+    last_seq: Option<u64>,
+    // finalized clients are kept for `consume_streaming_output` to write them
+    streaming: bool,
+    streamed: Vec<Client>,
+    // balances of clients removed by `tombstone_client`
+    tombstones: Vec<Client>,
+    // tombstones already written by `write_results_incremental`
+    emitted_tombstones: usize,
+}
+
+// entries the shards of `Bursar::consume_parallel` appended to their logs,
+// tagged with the clock value of the transaction which added them
+#[derive(Default)]
+struct ShardLogs {
+    violations: Vec<(u64, InvariantViolation)>,
+    rejects: Vec<(u64, (Transaction, TxError))>,
+    errors: Vec<(u64, TxError)>,
+    audit: Vec<(u64, AuditEntry)>,
+    processed_order: Vec<(u64, u32)>,
+}
+
+impl ShardLogs {
+    fn take(&mut self, shard: &mut Bursar) {
+        fn tagged<T>(seq: u64, entries: &mut Vec<T>) -> impl Iterator<Item = (u64, T)> + '_ {
+            entries.drain(..).map(move |entry| (seq, entry))
+        }
+        let seq = shard.clock;
+        self.violations.extend(tagged(seq, &mut shard.violations));
+        self.rejects.extend(tagged(seq, &mut shard.rejects));
+        self.errors.extend(tagged(seq, &mut shard.errors));
+        self.audit.extend(tagged(seq, &mut shard.audit));
+        self.processed_order
+            .extend(tagged(seq, &mut shard.processed_order));
+    }
+
+    fn extend(&mut self, other: ShardLogs) {
+        self.violations.extend(other.violations);
+        self.rejects.extend(other.rejects);
+        self.errors.extend(other.errors);
+        self.audit.extend(other.audit);
+        self.processed_order.extend(other.processed_order);
+    }
+
+    // appends the entries in the order the transactions were read in
+    fn append_to(self, bursar: &mut Bursar) {
+        fn ordered<T>(mut entries: Vec<(u64, T)>) -> impl Iterator<Item = T> {
+            entries.sort_by_key(|(seq, _)| *seq);
+            entries.into_iter().map(|(_, entry)| entry)
+        }
+        bursar.violations.extend(ordered(self.violations));
+        bursar.rejects.extend(ordered(self.rejects));
+        bursar.errors.extend(ordered(self.errors));
+        bursar.audit.extend(ordered(self.audit));
+        bursar.processed_order.extend(ordered(self.processed_order));
+    }
+}
+
+// state written by `Bursar::save_state`
+#[derive(Serialize)]
+struct SavedStateRef<'a> {
+    clock: u64,
+    clients: Vec<&'a Client>,
+    tombstones: &'a [Client],
+    transactions: HashMap<u32, TxRecord>,
+    disputed: &'a HashMap<u32, Dispute>,
+    flagged: &'a HashSet<u32>,
+    dispute_effects: &'a HashMap<u32, DisputeEffect>,
+    window: &'a VecDeque<(u64, u16, u32)>,
+    finalized: &'a HashSet<u16>,
+    queued_deposits: &'a HashMap<u16, Vec<Transaction>>,
+    processed: u64,
+    rejected: u64,
+    undisputed_chargebacks: u64,
+    slow_transactions: u64,
+    shadow_rejected: u64,
+    op_counts: &'a HashMap<Op, OpCounts>,
+    touched: &'a HashSet<u16>,
+    errors: &'a [TxError],
+    sealed: bool,
+    last_seq: Option<u64>,
+}
+
+// state read by `Bursar::load_state`
+#[derive(Deserialize)]
+struct SavedState {
+    clock: u64,
+    clients: Vec<Client>,
+    tombstones: Vec<Client>,
+    transactions: HashMap<u32, TxRecord>,
+    disputed: HashMap<u32, Dispute>,
+    flagged: HashSet<u32>,
+    dispute_effects: HashMap<u32, DisputeEffect>,
+    window: VecDeque<(u64, u16, u32)>,
+    finalized: HashSet<u16>,
+    queued_deposits: HashMap<u16, Vec<Transaction>>,
+    processed: u64,
+    rejected: u64,
+    undisputed_chargebacks: u64,
+    slow_transactions: u64,
+    shadow_rejected: u64,
+    op_counts: HashMap<Op, OpCounts>,
+    touched: HashSet<u16>,
+    errors: Vec<TxError>,
+    sealed: bool,
+    last_seq: Option<u64>,
+}
+
+impl Default for Bursar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bursar {
+    pub fn new() -> Self {
+        Bursar::with_config(BursarConfig::default())
+    }
+
+    pub fn with_config(config: BursarConfig) -> Self {
+        Bursar {
+            transactions: Box::new(HashMap::new()),
+            clients: Clients::new(config.client_map),
+            disputed: HashMap::new(),
+            flagged: HashSet::new(),
+            config,
+            clock: 0,
+            violations: Vec::new(),
+            reorder_buffer: VecDeque::new(),
+            window: VecDeque::new(),
+            finalized: HashSet::new(),
+            on_finalized: None,
+            rules: Vec::new(),
+            emitted_rows: HashMap::new(),
+            dispute_effects: HashMap::new(),
+            rejects: Vec::new(),
+            processed: 0,
+            rejected: 0,
+            undisputed_chargebacks: 0,
+            slow_transactions: 0,
+            shadow_rejected: 0,
+            op_counts: HashMap::new(),
+            touched: HashSet::new(),
+            processed_order: Vec::new(),
+            sealed: false,
+            queued_deposits: HashMap::new(),
+            audit: Vec::new(),
+            errors: Vec::new(),
+            last_seq: None,
+            streaming: false,
+            streamed: Vec::new(),
+            tombstones: Vec::new(),
+            emitted_tombstones: 0,
+        }
+    }
+
+    /// Seeds the clients from `(client, available, held, locked)` opening balances,
+    /// e.g. when migrating from another system. The opening total counts as an
+    /// inflow of the client.
+    pub fn from_opening_balances(
+        balances: impl Iterator<Item = (u16, Decimal, Decimal, bool)>,
+    ) -> Self {
+        let mut bursar = Bursar::new();
+        for (client_id, available, held, locked) in balances {
+            let mut client = Client::new(client_id);
+            client.available = available;
+            client.held = held;
+            client.deposited = available + held;
+            client.deposits = u32::from(!client.deposited.is_zero());
+            client.locked = locked;
+            client.locked_at = locked.then_some(0);
+            bursar.clients.insert(client);
+        }
+        bursar
+    }
+
+    /// Rebuilds the ledger by replaying the operations of an audit log, which
+    /// only holds applied ones. Policies altering balances on top, like
+    /// `NegativeTotalPolicy::ClampZero`, aren't part of the log.
+    pub fn from_audit_log(entries: &[AuditEntry]) -> Self {
+        let mut bursar = Bursar::new();
+        for entry in entries {
+            let amount = match entry.op {
+                Op::Deposit | Op::Withdrawal | Op::Dispute => Some(entry.amount),
+                Op::Resolve | Op::Chargeback | Op::Flag | Op::Unflag => None,
+            };
+            let tx = Transaction::new(entry.op, entry.client_id, entry.tx_id, amount);
+            if let Err(err) = bursar.try_process(tx) {
+                warn!("audit entry {:?} could not be replayed: {}", entry, err);
+            }
+        }
+        bursar
+    }
+
+    /// Writes the ledger state as JSON for `load_state` to resume processing
+    /// later, including the transactions which may still be disputed and what
+    /// `stats` and `summary` report. The config isn't part of it and has to be
+    /// applied again through the builders, e.g. `with_client_id_map`, nor are
+    /// the diagnostics rejects, violations, the audit log and the processing
+    /// order.
+    pub fn save_state<W: io::Write>(&self, w: W) -> io::Result<()> {
+        let state = SavedStateRef {
+            clock: self.clock,
+            clients: self.clients.values().collect(),
+            tombstones: &self.tombstones,
+            transactions: self.transactions.records().collect(),
+            disputed: &self.disputed,
+            flagged: &self.flagged,
+            dispute_effects: &self.dispute_effects,
+            window: &self.window,
+            finalized: &self.finalized,
+            queued_deposits: &self.queued_deposits,
+            processed: self.processed,
+            rejected: self.rejected,
+            undisputed_chargebacks: self.undisputed_chargebacks,
+            slow_transactions: self.slow_transactions,
+            shadow_rejected: self.shadow_rejected,
+            op_counts: &self.op_counts,
+            touched: &self.touched,
+            errors: &self.errors,
+            sealed: self.sealed,
+            last_seq: self.last_seq,
+        };
+        Ok(serde_json::to_writer(w, &state)?)
+    }
+
+    /// Restores a ledger written by `save_state` with the default config, the
+    /// builders apply on top of it.
+    pub fn load_state<R: io::Read>(r: R) -> io::Result<Bursar> {
+        let state: SavedState = serde_json::from_reader(r)?;
+        let mut bursar = Bursar::new();
+        state
+            .clients
+            .into_iter()
+            .for_each(|client| bursar.clients.insert(client));
+        bursar.tombstones = state.tombstones;
+        bursar.clock = state.clock;
+        bursar.transactions = Box::new(state.transactions);
+        bursar.disputed = state.disputed;
+        bursar.flagged = state.flagged;
+        bursar.dispute_effects = state.dispute_effects;
+        bursar.window = state.window;
+        bursar.finalized = state.finalized;
+        bursar.queued_deposits = state.queued_deposits;
+        bursar.processed = state.processed;
+        bursar.rejected = state.rejected;
+        bursar.undisputed_chargebacks = state.undisputed_chargebacks;
+        bursar.slow_transactions = state.slow_transactions;
+        bursar.shadow_rejected = state.shadow_rejected;
+        bursar.op_counts = state.op_counts;
+        bursar.touched = state.touched;
+        bursar.errors = state.errors;
+        bursar.sealed = state.sealed;
+        bursar.last_seq = state.last_seq;
+        Ok(bursar)
+    }
+
+    /// Keeps the transactions disputes may reference in `store` instead of an
+    /// unbounded `HashMap`, moving over the ones kept so far.
+    pub fn with_tx_store(mut self, store: impl TxStore + 'static) -> Self {
+        let mut store: Box<dyn TxStore> = Box::new(store);
+        for (tx_id, record) in self.transactions.records() {
+            store.insert(tx_id, record);
+        }
+        self.transactions = store;
+        self
+    }
+
+    pub fn with_client_map(mut self, kind: ClientMapKind) -> Self {
+        let clients = std::mem::replace(&mut self.clients, Clients::new(kind));
+        clients
+            .into_values()
+            .for_each(|client| self.clients.insert(client));
+        self.config.client_map = kind;
+        self
+    }
+
+    pub fn with_locked_summary(mut self, enabled: bool) -> Self {
+        self.config.log_locked_summary = enabled;
+        self
+    }
+
+    pub fn with_reorder_window(mut self, window: usize) -> Self {
+        self.config.reorder_window = window;
+        self
+    }
+
+    pub fn with_negative_total_policy(mut self, policy: NegativeTotalPolicy) -> Self {
+        self.config.negative_total_policy = policy;
+        self
+    }
+
+    pub fn with_locked_deposit_policy(mut self, policy: LockedDepositPolicy) -> Self {
+        self.config.locked_deposit_policy = policy;
+        self
+    }
+
+    pub fn with_rounding(mut self, rounding: Rounding) -> Self {
+        self.config.rounding = rounding;
+        self
+    }
+
+    pub fn with_stored_rounding(mut self, enabled: bool) -> Self {
+        self.config.round_stored_balances = enabled;
+        self
+    }
+
+    pub fn reject_on_precision_loss(mut self, enabled: bool) -> Self {
+        self.config.reject_on_precision_loss = enabled;
+        self
+    }
+
+    /// Panics on more than `MAX_PRECISION` decimal places.
+    pub fn with_precision(mut self, decimal_places: u32) -> Self {
+        assert!(
+            decimal_places <= MAX_PRECISION,
+            "precision must be at most {}",
+            MAX_PRECISION
+        );
+        self.config.precision = Some(decimal_places);
+        self
+    }
+
+    pub fn with_locked_repr(mut self, repr: LockedRepr) -> Self {
+        self.config.locked_repr = repr;
+        self
+    }
+
+    pub fn with_dispute_window(mut self, window: usize) -> Self {
+        self.config.dispute_window = window;
+        self
+    }
+
+    /// Finalizes clients without any activity or open dispute inside the dispute
+    /// window, handing them to `callback` and dropping them from memory. Later
+    /// transactions for a finalized client are rejected.
+    pub fn on_client_finalized(
+        mut self,
+        callback: impl FnMut(ClientSnapshot) + Send + 'static,
+    ) -> Self {
+        self.on_finalized = Some(Box::new(callback));
+        self
+    }
+
+    /// Adds a validation rule, `check` returning false fails the transaction.
+    pub fn with_rule(
+        mut self,
+        name: &str,
+        mode: RuleMode,
+        check: impl Fn(&Transaction) -> bool + Send + 'static,
+    ) -> Self {
+        self.rules.push(ValidationRule {
+            name: name.to_string(),
+            mode,
+            check: Box::new(check),
+        });
+        self
+    }
+
+    pub fn with_reject_tracking(mut self, enabled: bool) -> Self {
+        self.config.track_rejects = enabled;
+        self
+    }
+
+    pub fn with_client_id_map(mut self, client_id_map: HashMap<u16, u16>) -> Self {
+        self.config.client_id_map = client_id_map;
+        self
+    }
+
+    pub fn with_unmapped_clients_rejected(mut self, enabled: bool) -> Self {
+        self.config.reject_unmapped_clients = enabled;
+        self
+    }
+
+    pub fn require_registered_clients(mut self, enabled: bool) -> Self {
+        self.config.require_registered_clients = enabled;
+        self
+    }
+
+    /// Adds a client without any funds, by its id after `BursarConfig::client_id_map`.
+    /// Does nothing on a sealed ledger.
+    pub fn register_client(&mut self, client_id: u16) {
+        if !self.sealed {
+            self.clients.get_or_create(client_id);
+        }
+    }
+
+    pub fn with_disputes_requiring_funds(mut self, enabled: bool) -> Self {
+        self.config.disputes_require_funds = enabled;
+        self
+    }
+
+    pub fn with_dispute_recency(mut self, transactions: u32) -> Self {
+        self.config.dispute_recency = Some(transactions);
+        self
+    }
+
+    pub fn with_max_held_ratio(mut self, ratio: Decimal) -> Self {
+        self.config.max_held_ratio = Some(ratio);
+        self
+    }
+
+    pub fn with_uncovered_disputes_capped(mut self, enabled: bool) -> Self {
+        self.config.cap_uncovered_disputes = enabled;
+        self
+    }
+
+    pub fn with_partial_withdrawals(mut self, enabled: bool) -> Self {
+        self.config.partial_withdrawals = enabled;
+        self
+    }
+
+    pub fn with_transaction_budget(mut self, budget: Duration) -> Self {
+        self.config.transaction_budget = Some(budget);
+        self
+    }
+
+    pub fn with_audit_log(mut self, enabled: bool) -> Self {
+        self.config.audit_log = enabled;
+        self
+    }
+
+    pub fn with_undisputed_chargeback_error(mut self, enabled: bool) -> Self {
+        self.config.undisputed_chargeback_is_error = enabled;
+        self
+    }
+
+    pub fn with_processing_order(mut self, enabled: bool) -> Self {
+        self.config.track_processing_order = enabled;
+        self
+    }
+
+    pub fn with_output_buffer(mut self, capacity: usize) -> Self {
+        self.config.output_buffer = Some(capacity);
+        self
+    }
+
+    pub fn with_client_id_width(mut self, width: usize) -> Self {
+        self.config.client_id_width = width;
+        self
+    }
+
+    pub fn with_checksum_column(mut self, enabled: bool) -> Self {
+        self.config.checksum_column = enabled;
+        self
+    }
+
+    pub fn with_zero_epsilon(mut self, epsilon: Decimal) -> Self {
+        self.config.zero_epsilon = epsilon;
+        self
+    }
+
+    pub fn with_zero_balances_skipped(mut self, enabled: bool) -> Self {
+        self.config.skip_zero_balances = enabled;
+        self
+    }
+
+    pub fn consume(&mut self, transactions: impl Iterator<Item = Transaction>) {
+        self.feed(transactions);
+        self.end_of_stream();
+    }
+
+    /// Same as `consume` in the order of the transactions' timestamps, the ones
+    /// without a timestamp first. Ties are broken by the tx id and then by the
+    /// input order, so equal records are processed as they came in.
+    pub fn consume_ordered(&mut self, transactions: impl Iterator<Item = Transaction>) {
+        let mut transactions: Vec<(usize, Transaction)> = transactions.enumerate().collect();
+        transactions.sort_by_key(|(index, tx)| (tx.timestamp, tx.tx_id, *index));
+        self.consume(transactions.into_iter().map(|(_, tx)| tx));
+    }
+
+    /// Same as `consume` for part of a stream, resolves waiting in the reorder
+    /// buffer stay there for the following parts until `finish`.
+    pub fn feed(&mut self, transactions: impl Iterator<Item = Transaction>) {
+        transactions.for_each(|tx| self.reorder(tx));
+    }
+
+    /// Ends a stream handed over through `feed`, processing the resolves still
+    /// waiting for their dispute.
+    pub fn finish(&mut self) {
+        self.end_of_stream();
+    }
+
+    /// Takes the reorder buffer out along with the logical clock, for `resume`
+    /// to continue the stream later, e.g. on a ledger restored by `load_state`.
+    pub fn pause(&mut self) -> PausedState {
+        PausedState {
+            clock: self.clock,
+            reorder_buffer: std::mem::take(&mut self.reorder_buffer),
+        }
+    }
+
+    /// Picks up a stream paused by `pause`, the restored resolves keep the
+    /// positions they expire at.
+    pub fn resume(&mut self, state: PausedState) {
+        self.clock = self.clock.max(state.clock);
+        let mut reorder_buffer = state.reorder_buffer;
+        reorder_buffer.append(&mut self.reorder_buffer);
+        self.reorder_buffer = reorder_buffer;
+    }
+
+    /// Same as `consume` for sources which may fail to produce a transaction, like
+    /// `csv::Reader::deserialize`. Stops at the first error and returns it, the
+    /// transactions before it are processed.
+    pub fn try_consume<E>(
+        &mut self,
+        mut transactions: impl Iterator<Item = Result<Transaction, E>>,
+    ) -> Result<(), E> {
+        let result = transactions.try_for_each(|tx| tx.map(|tx| self.reorder(tx)));
+        self.end_of_stream();
+        result
+    }
+
+    /// Same as `consume`, spreading the clients over `workers` threads. Every
+    /// transaction only touches its own client, so each thread runs a `Bursar`
+    /// of its own on a shard of the clients and the shards are merged back at
+    /// the end. The shards keep their transactions in memory until they are
+    /// merged into the `TxStore` of the ledger.
+    ///
+    /// Each transaction is processed at the clock value it has in `consume`,
+    /// the logs like `errors` and `rejects` are merged back in input order.
+    ///
+    /// Falls back to `consume` for settings counting transactions across
+    /// clients (`dispute_window`, `dispute_recency`, `reorder_window`), with a
+    /// finalization callback or validation rules and on a sealed ledger. As a
+    /// shard only knows the transaction ids of its own clients, the input is
+    /// read into memory up front and handed to `consume` as well if a client
+    /// uses or references the id of a transaction of another client.
+    pub fn consume_parallel(
+        &mut self,
+        transactions: impl Iterator<Item = Transaction>,
+        workers: usize,
+    ) {
+        if workers <= 1
+            || self.config.dispute_window > 0
+            || self.config.dispute_recency.is_some()
+            || self.config.reorder_window > 0
+            || self.on_finalized.is_some()
+            || !self.rules.is_empty()
+            || self.streaming
+            || self.sealed
+        {
+            return self.consume(transactions);
+        }
+        let transactions: Vec<Transaction> = transactions.collect();
+        if self.shares_tx_ids(&transactions) {
+            return self.consume(transactions.into_iter());
+        }
+        let start = self.clock;
+        let shards: Vec<Bursar> = (0..workers)
+            .map(|i| self.split_off(|client| client.client_id as usize % workers == i))
+            .collect();
+
+        // shard by the mapped id, the one the client ends up under
+        let client_id_map = &self.config.client_id_map;
+        let mut clock = start;
+        let shards: Vec<(Bursar, ShardLogs)> = thread::scope(|scope| {
+            let (senders, handles): (Vec<_>, Vec<_>) = shards
+                .into_iter()
+                .map(|mut shard| {
+                    let (sender, receiver) =
+                        mpsc::sync_channel::<(u64, Transaction)>(PARALLEL_QUEUE);
+                    let handle = scope.spawn(move || {
+                        let mut logs = ShardLogs::default();
+                        for (seq, tx) in receiver {
+                            // the clock value the transaction is processed at in `consume`
+                            shard.clock = seq;
+                            shard.process_transaction(tx);
+                            logs.take(&mut shard);
+                        }
+                        (shard, logs)
+                    });
+                    (sender, handle)
+                })
+                .unzip();
+            for tx in transactions {
+                let client_id = *client_id_map.get(&tx.client_id).unwrap_or(&tx.client_id);
+                // a worker only hangs up by panicking, reported by the join below
+                let _ = senders[client_id as usize % workers].send((clock, tx));
+                clock += 1;
+            }
+            drop(senders);
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("consume worker panicked"))
+                .collect()
+        });
+
+        let mut merged = ShardLogs::default();
+        for (shard, logs) in shards {
+            self.absorb(shard);
+            merged.extend(logs);
+        }
+        merged.append_to(self);
+        self.clock = clock;
+        if self.config.log_locked_summary {
+            self.log_locked_summary();
+        }
+    }
+
+    // whether a transaction id is used or referenced by more than one client,
+    // the transactions of other clients being out of reach of a shard
+    fn shares_tx_ids(&mut self, transactions: &[Transaction]) -> bool {
+        let mut owners: HashMap<u32, u16> = HashMap::new();
+        for tx in transactions {
+            let client_id = *self
+                .config
+                .client_id_map
+                .get(&tx.client_id)
+                .unwrap_or(&tx.client_id);
+            let owner = match owners.get(&tx.tx_id) {
+                Some(owner) => Some(*owner),
+                None => self
+                    .transactions
+                    .get(tx.tx_id)
+                    .map(|record| record.client_id),
+            };
+            if owner.is_some_and(|owner| owner != client_id) {
+                return true;
+            }
+            if matches!(tx.tx_type, Op::Deposit | Op::Withdrawal) {
+                owners.insert(tx.tx_id, client_id);
+            }
+        }
+        false
+    }
+
+    // merges a shard of `consume_parallel`, its logs are merged by `ShardLogs`
+    fn absorb(&mut self, shard: Bursar) {
+        for client in shard.clients.into_values() {
+            self.clients.insert(client);
+        }
+        for (tx_id, record) in shard.transactions.records() {
+            self.transactions.insert(tx_id, record);
+        }
+        self.disputed.extend(shard.disputed);
+        self.flagged.extend(shard.flagged);
+        self.dispute_effects.extend(shard.dispute_effects);
+        self.queued_deposits.extend(shard.queued_deposits);
+        self.emitted_rows.extend(shard.emitted_rows);
+        self.finalized.extend(shard.finalized);
+        self.processed += shard.processed;
+        self.rejected += shard.rejected;
+        self.undisputed_chargebacks += shard.undisputed_chargebacks;
+        self.slow_transactions += shard.slow_transactions;
+        self.shadow_rejected += shard.shadow_rejected;
+        for (op, counts) in shard.op_counts {
+            let merged = self.op_counts.entry(op).or_default();
+            merged.applied += counts.applied;
+            merged.rejected += counts.rejected;
+        }
+        self.touched.extend(shard.touched);
+        self.last_seq = self.last_seq.max(shard.last_seq);
+    }
+
+    /// Processes the csv files one after another as a single stream, so disputes
+    /// may reference transactions of earlier files. Records which can't be parsed
+    /// are skipped, a file which can't be opened or read stops the run.
+    pub fn consume_files(&mut self, paths: &[&Path]) -> io::Result<()> {
+        for path in paths {
+            self.consume_source(fs::File::open(path)?)?;
+        }
+        self.end_of_stream();
+        Ok(())
+    }
+
+    /// Processes the transactions of a csv source like a file or stdin, records
+    /// which can't be parsed are skipped.
+    pub fn consume_reader<R: io::Read>(&mut self, source: R) -> io::Result<()> {
+        self.consume_sources(std::iter::once(source))
+    }
+
+    /// Same as `consume_files` for csv sources which are already open.
+    pub fn consume_sources<R: io::Read>(
+        &mut self,
+        sources: impl IntoIterator<Item = R>,
+    ) -> io::Result<()> {
+        for source in sources {
+            self.consume_source(source)?;
+        }
+        self.end_of_stream();
+        Ok(())
+    }
+
+    fn consume_source<R: io::Read>(&mut self, source: R) -> io::Result<()> {
+        let mut reader = transaction_reader(source, &HashMap::new())?;
+        read_transactions(&mut reader).for_each(|tx| self.reorder(tx));
+        Ok(())
+    }
+
+    /// Processes the csv `source` while writing the results to `target`, rows of
+    /// clients finalized in the dispute window mode are written and flushed right
+    /// away, the remaining ones once the source is exhausted.
+    pub fn consume_streaming_output<R: io::Read, W: io::Write>(
+        &mut self,
+        source: R,
+        target: W,
+    ) -> csv::Result<()> {
+        let mut reader = transaction_reader(source, &HashMap::new())?;
+        let mut writer = WriterBuilder::new().from_writer(target);
+        self.streaming = true;
+        for tx in read_transactions(&mut reader) {
+            self.reorder(tx);
+            if !self.streamed.is_empty() {
+                for client in std::mem::take(&mut self.streamed) {
+                    writer.serialize(self.row(&client))?;
+                }
+                writer.flush()?;
+            }
+        }
+        self.streaming = false;
+        self.end_of_stream();
+        for client in std::mem::take(&mut self.streamed) {
+            writer.serialize(self.row(&client))?;
+        }
+        self.write_rows(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Same as `consume` but gives up on a presumably corrupt source once more than
+    /// `max_error_rate` of its transactions got rejected. The rate is only checked
+    /// after the first few transactions. Giving up ends the stream like `consume`
+    /// does: the transactions processed until then stay applied and the resolves
+    /// still waiting for their dispute get processed.
+    pub fn consume_with_error_budget(
+        &mut self,
+        transactions: impl Iterator<Item = Transaction>,
+        max_error_rate: f64,
+    ) -> Result<(), ErrorBudgetExceeded> {
+        let rejected_before = self.rejected;
+        let mut processed = 0;
+        for tx in transactions {
+            self.reorder(tx);
+            processed += 1;
+            let rejected = self.rejected - rejected_before;
+            if processed >= ERROR_BUDGET_SAMPLE
+                && rejected as f64 > max_error_rate * processed as f64
+            {
+                error!(
+                    "error budget exceeded, {} of {} rejected",
+                    rejected, processed
+                );
+                self.end_of_stream();
+                return Err(ErrorBudgetExceeded {
+                    processed,
+                    rejected,
+                });
+            }
+        }
+        self.end_of_stream();
+        Ok(())
+    }
+
+    /// Same as `consume`, handing the current stats to `sink` after every `every` transactions.
+    pub fn consume_with_snapshots(
+        &mut self,
+        transactions: impl Iterator<Item = Transaction>,
+        every: usize,
+        mut sink: impl FnMut(BursarStats),
+    ) {
+        assert!(every > 0, "snapshot interval must be positive");
+        for (i, tx) in transactions.enumerate() {
+            self.reorder(tx);
+            if (i + 1) % every == 0 {
+                sink(self.stats());
+            }
+        }
+        self.end_of_stream();
+    }
+
+    fn end_of_stream(&mut self) {
+        self.flush_reorder_buffer();
+        if self.config.log_locked_summary {
+            self.log_locked_summary();
+        }
+    }
+
+    fn flush_reorder_buffer(&mut self) {
+        while let Some((_, tx)) = self.reorder_buffer.pop_front() {
+            self.process_transaction(tx);
+        }
+    }
+
+    /// Applied and rejected transactions per operation type with the reasons
+    /// for rejecting them.
+    pub fn summary(&self) -> Summary {
+        let counts = |op| self.op_counts.get(&op).copied().unwrap_or_default();
+        let mut rejected_by_reason = BTreeMap::new();
+        for err in &self.errors {
+            *rejected_by_reason.entry(err.as_str()).or_default() += 1;
+        }
+        Summary {
+            deposits: counts(Op::Deposit),
+            withdrawals: counts(Op::Withdrawal),
+            disputes: counts(Op::Dispute),
+            resolves: counts(Op::Resolve),
+            chargebacks: counts(Op::Chargeback),
+            flags: counts(Op::Flag),
+            unflags: counts(Op::Unflag),
+            rejected_by_reason,
+            clients: self.touched.len(),
+        }
+    }
+
+    pub fn stats(&self) -> BursarStats {
+        let mut stats = BursarStats {
+            processed: self.processed,
+            rejected: self.rejected,
+            undisputed_chargebacks: self.undisputed_chargebacks,
+            slow_transactions: self.slow_transactions,
+            shadow_rejected: self.shadow_rejected,
+            clients: 0,
+            locked: 0,
+            available: Decimal::ZERO,
+            held: Decimal::ZERO,
+        };
+        for client in self.clients.values().chain(&self.tombstones) {
+            stats.clients += 1;
+            stats.locked += usize::from(client.locked);
+            stats.available += client.available;
+            stats.held += client.held;
+        }
+        stats
+    }
+
+    // holds back resolves of undisputed transactions until their dispute shows
+    // up or `reorder_window` further transactions have been processed
+    fn reorder(&mut self, tx: Transaction) {
+        if self.config.reorder_window > 0
+            && matches!(tx.tx_type, Op::Resolve)
+            && !self.disputed.contains_key(&tx.tx_id)
+        {
+            let expires_at = self.clock + self.config.reorder_window as u64;
+            self.reorder_buffer.push_back((expires_at, tx));
+            return;
+        }
+
+        let dispute = matches!(tx.tx_type, Op::Dispute).then_some(tx.tx_id);
+        self.process_transaction(tx);
+        if let Some(tx_id) = dispute.filter(|tx_id| self.disputed.contains_key(tx_id)) {
+            let waiting = self
+                .reorder_buffer
+                .iter()
+                .position(|(_, resolve)| resolve.tx_id == tx_id);
+            if let Some((_, resolve)) = waiting.and_then(|i| self.reorder_buffer.remove(i)) {
+                self.process_transaction(resolve);
+            }
+        }
+
+        while let Some((expires_at, _)) = self.reorder_buffer.front() {
+            if *expires_at > self.clock {
+                break;
+            }
+            let (_, expired) = self.reorder_buffer.pop_front().unwrap();
+            self.process_transaction(expired);
+        }
+    }
+
+    fn log_locked_summary(&self) {
+        let mut locked: Vec<u16> = self
+            .clients
+            .values()
+            .filter(|client| client.locked)
+            .map(|client| client.client_id)
+            .collect();
+        locked.sort_unstable();
+        let ids: Vec<String> = locked.iter().map(|id| id.to_string()).collect();
+        info!("locked accounts: {} [{}]", locked.len(), ids.join(","));
+    }
+
+    pub fn client_balance(&self, client_id: u16) -> Option<ClientSnapshot> {
+        self.clients
+            .get(&client_id)
+            .map(|client| client.snapshot(self.clock))
+    }
+
+    /// Snapshots of all clients ordered by client id.
+    pub fn clients(&self) -> impl Iterator<Item = ClientSnapshot> + '_ {
+        self.clients
+            .sorted()
+            .into_iter()
+            .map(|client| client.snapshot(self.clock))
+    }
+
+    /// Disputed amount of the client's open disputes which couldn't be held as it
+    /// wasn't available anymore, only with capped disputes.
+    pub fn uncovered(&self, client_id: u16) -> Option<Decimal> {
+        self.clients.get(&client_id).map(|client| client.uncovered)
+    }
+
+    /// Available and held of the client at full precision, without the rounding
+    /// applied to the output.
+    pub fn exact_balances(&self, client_id: u16) -> Option<(Decimal, Decimal)> {
+        self.clients
+            .get(&client_id)
+            .map(|client| (client.available, client.held))
+    }
+
+    /// Client holding the most funds with its held amount, the lowest id among
+    /// equal ones.
+    pub fn max_exposure_client(&self) -> Option<(u16, Decimal)> {
+        self.clients
+            .values()
+            .map(|client| (client.client_id, client.held))
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+    }
+
+    /// Mean amount of the client's deposits, `None` without any deposit. An
+    /// opening balance counts as one deposit.
+    pub fn avg_deposit(&self, client_id: u16) -> Option<Decimal> {
+        self.clients
+            .get(&client_id)
+            .filter(|client| client.deposits > 0)
+            .map(|client| client.deposited / Decimal::from(client.deposits))
+    }
+
+    /// Deposits minus withdrawals of the client, regardless of any disputes.
+    pub fn net_flow(&self, client_id: u16) -> Option<Decimal> {
+        self.clients.get(&client_id).map(Client::net_flow)
+    }
+
+    /// Deposits and withdrawals which are kept around to be referenced.
+    pub fn retained_transactions(&self) -> impl Iterator<Item = (u32, Decimal)> + '_ {
+        self.transactions
+            .records()
+            .map(|(tx_id, record)| (tx_id, record.amount))
+    }
+
+    /// Rough estimate of the bytes held by the clients, transactions and disputes,
+    /// the entry counts times the size of key and value. Ignores allocator and
+    /// hash table overhead as well as spare capacity, so the real usage is higher.
+    pub fn approx_memory_bytes(&self) -> usize {
+        use std::mem::size_of;
+        self.clients.len() * (size_of::<u16>() + size_of::<Client>())
+            + self.transactions.len() * (size_of::<u32>() + size_of::<TxRecord>())
+            + self.disputed.len() * (size_of::<u32>() + size_of::<Dispute>())
+    }
+
+    /// Operations applied so far, only recorded with the audit log enabled.
+    pub fn audit_log(&self) -> &[AuditEntry] {
+        &self.audit
+    }
+
+    /// Ids of all processed transactions in processing order, rejected ones
+    /// included. Only recorded with processing order tracking enabled.
+    pub fn processed_order(&self) -> &[u32] {
+        &self.processed_order
+    }
+
+    /// External sequence of the last applied transaction tagged with one, e.g. the
+    /// offset a queue consumer can commit.
+    pub fn last_processed_seq(&self) -> Option<u64> {
+        self.last_seq
+    }
+
+    /// Reasons of all rejected transactions in processing order, see `rejects`
+    /// for the transactions themselves.
+    pub fn errors(&self) -> &[TxError] {
+        &self.errors
+    }
+
+    /// Rejected transactions with their reason, only kept with reject tracking enabled.
+    pub fn rejects(&self) -> &[(Transaction, TxError)] {
+        &self.rejects
+    }
+
+    /// Number of applied disputes, resolves and chargebacks of the client.
+    pub fn dispute_activity(&self, client_id: u16) -> Option<(u32, u32, u32)> {
+        self.clients
+            .get(&client_id)
+            .map(|client| (client.disputes, client.resolves, client.chargebacks))
+    }
+
+    /// Disputes all of `tx_ids` or, if any of them can't be disputed, none of them.
+    /// The client id is mapped like the one of a transaction.
+    pub fn dispute_batch(
+        &mut self,
+        client_id: u16,
+        tx_ids: &[u32],
+    ) -> Result<(), BatchDisputeError> {
+        if self.sealed {
+            return Err(BatchDisputeError::Sealed);
+        }
+        let client_id = self
+            .mapped_client_id(client_id)
+            .map_err(|_| BatchDisputeError::UnmappedClient)?;
+        let mut batch = HashSet::new();
+        let mut amount = Decimal::ZERO;
+        for tx_id in tx_ids {
+            if self.disputed.contains_key(tx_id) || !batch.insert(*tx_id) {
+                return Err(BatchDisputeError::AlreadyDisputed(*tx_id));
+            }
+            match self.transactions.get(*tx_id) {
+                Some(record) if record.client_id != client_id => {
+                    return Err(BatchDisputeError::ForeignTransaction(*tx_id))
+                }
+                Some(record) if record.op == Op::Withdrawal => {
+                    return Err(BatchDisputeError::Withdrawal(*tx_id))
+                }
+                Some(record) => amount += record.amount,
+                None => return Err(BatchDisputeError::UnknownTransaction(*tx_id)),
+            }
+        }
+        if let Some(client) = self.clients.get(&client_id) {
+            if client.held + amount > client.deposited {
+                return Err(BatchDisputeError::ExceedsInflows);
+            }
+        }
+
+        // the disputes go through `try_process` for all of its checks and are
+        // undone on the first rejection, the dispute window is only advanced
+        // once all of them got applied as it can't be undone
+        let checkpoint = self.checkpoint(client_id, tx_ids);
+        let dispute_window = std::mem::take(&mut self.config.dispute_window);
+        let mut applied = Vec::new();
+        for tx_id in tx_ids {
+            let dispute = Transaction::new(Op::Dispute, client_id, *tx_id, None);
+            if let Err(err) = self.process(dispute, true) {
+                self.config.dispute_window = dispute_window;
+                self.restore(checkpoint, client_id, tx_ids);
+                return Err(BatchDisputeError::Rejected(*tx_id, err));
+            }
+            applied.push((self.clock, client_id, *tx_id));
+        }
+        self.config.dispute_window = dispute_window;
+        if dispute_window > 0 {
+            self.window.extend(applied);
+            self.advance_window();
+        }
+        Ok(())
+    }
+
+    fn checkpoint(&self, client_id: u16, tx_ids: &[u32]) -> BatchCheckpoint {
+        BatchCheckpoint {
+            client: self.clients.get(&client_id).cloned(),
+            flagged: tx_ids
+                .iter()
+                .copied()
+                .filter(|tx_id| self.flagged.contains(tx_id))
+                .collect(),
+            dispute_effects: tx_ids
+                .iter()
+                .filter_map(|tx_id| Some((*tx_id, self.dispute_effects.get(tx_id)?.clone())))
+                .collect(),
+            clock: self.clock,
+            processed: self.processed,
+            rejected: self.rejected,
+            undisputed_chargebacks: self.undisputed_chargebacks,
+            slow_transactions: self.slow_transactions,
+            shadow_rejected: self.shadow_rejected,
+            op_counts: self.op_counts.clone(),
+            touched: self.touched.contains(&client_id),
+            logs: [
+                self.processed_order.len(),
+                self.errors.len(),
+                self.rejects.len(),
+                self.audit.len(),
+                self.violations.len(),
+            ],
+        }
+    }
+
+    fn restore(&mut self, checkpoint: BatchCheckpoint, client_id: u16, tx_ids: &[u32]) {
+        match checkpoint.client {
+            Some(client) => self.clients.insert(client),
+            None => {
+                self.clients.remove(&client_id);
+            }
+        }
+        for tx_id in tx_ids {
+            // none of them was disputed before the batch
+            self.disputed.remove(tx_id);
+            self.dispute_effects.remove(tx_id);
+        }
+        self.flagged.extend(checkpoint.flagged);
+        self.dispute_effects.extend(checkpoint.dispute_effects);
+        self.clock = checkpoint.clock;
+        self.processed = checkpoint.processed;
+        self.rejected = checkpoint.rejected;
+        self.undisputed_chargebacks = checkpoint.undisputed_chargebacks;
+        self.slow_transactions = checkpoint.slow_transactions;
+        self.shadow_rejected = checkpoint.shadow_rejected;
+        self.op_counts = checkpoint.op_counts;
+        if !checkpoint.touched {
+            self.touched.remove(&client_id);
+        }
+        let [processed_order, errors, rejects, audit, violations] = checkpoint.logs;
+        self.processed_order.truncate(processed_order);
+        self.errors.truncate(errors);
+        self.rejects.truncate(rejects);
+        self.audit.truncate(audit);
+        self.violations.truncate(violations);
+    }
+
+    /// Processes `tx` only if its client is still at `expected_version`, unknown
+    /// clients being at version 0.
+    pub fn process_if_version(
+        &mut self,
+        tx: Transaction,
+        expected_version: u64,
+    ) -> Result<(), VersionMismatch> {
+        let client_id = self.mapped_client_id(tx.client_id).unwrap_or(tx.client_id);
+        let actual = self
+            .clients
+            .get(&client_id)
+            .map_or(0, |client| client.version);
+        if actual != expected_version {
+            return Err(VersionMismatch {
+                expected: expected_version,
+                actual,
+            });
+        }
+        self.process_transaction(tx);
+        Ok(())
+    }
+
+    /// Marks the ledger as final, further transactions are refused with
+    /// `TxError::Sealed` and leave the state untouched. Reading the results
+    /// keeps working.
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    pub fn is_sealed(&self) -> bool {
+        self.sealed
+    }
+
+    /// Moves the clients matching `pred` together with their retained transactions
+    /// and disputes into a new `Bursar` sharing the configuration, e.g. to archive
+    /// inactive accounts. The finalization callback stays with `self`. A sealed
+    /// ledger keeps its clients, the returned one is empty.
+    pub fn split_off(&mut self, pred: impl Fn(&ClientSnapshot) -> bool) -> Bursar {
+        let mut other = Bursar::with_config(self.config.clone());
+        other.clock = self.clock;
+        if self.sealed {
+            return other;
+        }
+        let client_ids: Vec<u16> = self
+            .clients
+            .values()
+            .filter(|client| pred(&client.snapshot(self.clock)))
+            .map(|client| client.client_id)
+            .collect();
+        for client_id in &client_ids {
+            if let Some(client) = self.clients.remove(client_id) {
+                other.clients.insert(client);
+            }
+            if let Some(queued) = self.queued_deposits.remove(client_id) {
+                other.queued_deposits.insert(*client_id, queued);
+            }
+            if let Some(rows) = self.emitted_rows.remove(client_id) {
+                other.emitted_rows.insert(*client_id, rows);
+            }
+            if self.finalized.remove(client_id) {
+                other.finalized.insert(*client_id);
+            }
+        }
+        let moved = |client_id: &u16| client_ids.contains(client_id);
+
+        let moved_tx_ids: Vec<u32> = self
+            .transactions
+            .records()
+            .filter(|(_, record)| moved(&record.client_id))
+            .map(|(tx_id, _)| tx_id)
+            .collect();
+        let mut transactions = HashMap::new();
+        for tx_id in moved_tx_ids {
+            if let Some(record) = self.transactions.remove(tx_id) {
+                transactions.insert(tx_id, record);
+            }
+        }
+        let (disputed, kept): (HashMap<_, _>, HashMap<_, _>) = std::mem::take(&mut self.disputed)
+            .into_iter()
+            .partition(|(_, dispute)| moved(&dispute.client_id));
+        self.disputed = kept;
+        let (effects, kept): (HashMap<_, _>, HashMap<_, _>) =
+            std::mem::take(&mut self.dispute_effects)
+                .into_iter()
+                .partition(|(tx_id, _)| transactions.contains_key(tx_id));
+        self.dispute_effects = kept;
+        let (flagged, kept): (HashSet<_>, HashSet<_>) = std::mem::take(&mut self.flagged)
+            .into_iter()
+            .partition(|tx_id| transactions.contains_key(tx_id));
+        self.flagged = kept;
+
+        other.transactions = Box::new(transactions);
+        other.disputed = disputed;
+        other.dispute_effects = effects;
+        other.flagged = flagged;
+        other
+    }
+
+    /// Detaches the balances of a client from its id, e.g. on a request for
+    /// erasure. The balances stay part of the output under `TOMBSTONE_CLIENT`
+    /// while the client and its transactions are gone from every lookup, a
+    /// later transaction for the id starts a new client. Does nothing on a
+    /// sealed ledger.
+    pub fn tombstone_client(&mut self, client_id: u16) {
+        if self.sealed {
+            return;
+        }
+        let removed = self.split_off(|client| client.client_id == client_id);
+        self.tombstones.extend(removed.clients.into_values());
+    }
+
+    fn is_tombstoned(&self, client_id: u16) -> bool {
+        self.tombstones
+            .iter()
+            .any(|client| client.client_id == client_id)
+    }
+
+    /// Lifts the lock of a client, e.g. after its chargeback got reversed, and
+    /// applies the deposits queued while it was locked. Returns whether the
+    /// client was locked.
+    pub fn unlock(&mut self, client_id: u16) -> bool {
+        match self.clients.get_mut(&client_id) {
+            Some(client) if client.locked && !self.sealed => {
+                client.locked = false;
+                client.locked_at = None;
+            }
+            _ => return false,
+        }
+        for tx in self.queued_deposits.remove(&client_id).unwrap_or_default() {
+            let _ = self.apply_counted(tx);
+        }
+        true
+    }
+
+    /// Resolves every open dispute of the client, returning how many got resolved.
+    /// The client id is mapped like the one of a transaction.
+    pub fn resolve_all(&mut self, client_id: u16) -> usize {
+        let Ok(client_id) = self.mapped_client_id(client_id) else {
+            return 0;
+        };
+        let mut tx_ids: Vec<u32> = self
+            .disputed
+            .iter()
+            .filter(|(_, dispute)| dispute.client_id == client_id)
+            .map(|(tx_id, _)| *tx_id)
+            .collect();
+        tx_ids.sort_unstable();
+        tx_ids
+            .into_iter()
+            .filter(|tx_id| {
+                self.process(Transaction::new(Op::Resolve, client_id, *tx_id, None), true)
+                    .is_ok()
+            })
+            .count()
+    }
+
+    pub fn process_transaction(&mut self, tx: Transaction) {
+        let _ = self.try_process(tx);
+    }
+
+    /// Like `process_transaction`, returning why the transaction was rejected.
+    pub fn try_process(&mut self, tx: Transaction) -> Result<(), TxError> {
+        self.process(tx, false)
+    }
+
+    // `mapped` transactions already carry the client id `with_client_id_map` maps to
+    fn process(&mut self, mut tx: Transaction, mapped: bool) -> Result<(), TxError> {
+        if self.sealed {
+            error!("transaction '{:?}' after sealing ignored", tx.tx_id);
+            return Err(TxError::Sealed);
+        }
+        self.processed += 1;
+        // ticks for rejected transactions as well, up front so it doesn't
+        // depend on the check they fail
+        self.clock += 1;
+        if self.config.track_processing_order {
+            self.processed_order.push(tx.tx_id);
+        }
+        match self.mapped_client_id(tx.client_id) {
+            _ if mapped => {}
+            Ok(client_id) => {
+                tx.input_client_id = Some(tx.client_id);
+                tx.client_id = client_id;
+            }
+            Err(err) => {
+                self.reject(tx, err);
+                return Err(err);
+            }
+        }
+        self.touched.insert(tx.client_id);
+        if self.config.require_registered_clients && self.clients.get(&tx.client_id).is_none() {
+            self.reject(tx, TxError::UnregisteredClient);
+            return Err(TxError::UnregisteredClient);
+        }
+        if self.finalized.contains(&tx.client_id) {
+            self.reject(tx, TxError::ClientFinalized);
+            return Err(TxError::ClientFinalized);
+        }
+        let mut shadow_rejected = false;
+        for rule in self.rules.iter().filter(|rule| !(rule.check)(&tx)) {
+            match rule.mode {
+                RuleMode::Enforce => {
+                    warn!("transaction '{:?}' fails rule '{}'", tx.tx_id, rule.name);
+                    self.reject(tx, TxError::RuleViolation);
+                    return Err(TxError::RuleViolation);
+                }
+                RuleMode::Shadow => {
+                    info!(
+                        "transaction '{:?}' would be rejected by shadow rule '{}'",
+                        tx.tx_id, rule.name
+                    );
+                    shadow_rejected = true;
+                }
+            }
+        }
+        self.shadow_rejected += u64::from(shadow_rejected);
+        let client_id = tx.client_id;
+        if tx.tx_type == Op::Deposit
+            && self.config.locked_deposit_policy == LockedDepositPolicy::Queue
+            && self
+                .clients
+                .get(&client_id)
+                .is_some_and(|client| client.locked)
+        {
+            self.queued_deposits.entry(client_id).or_default().push(tx);
+            return Ok(());
+        }
+        self.apply_counted(tx)
+    }
+
+    // applies the transaction, counting it as applied or rejected
+    fn apply_counted(&mut self, tx: Transaction) -> Result<(), TxError> {
+        let (client_id, tx_id) = (tx.client_id, tx.tx_id);
+        let known = self.clients.get(&client_id).is_some();
+        let started = Instant::now();
+        let result = self.apply(&tx);
+        if result.is_err() && !known && self.is_tombstoned(client_id) {
+            // the rejected transaction mustn't bring back an empty client for the id
+            self.clients.remove(&client_id);
+        }
+        if let (Ok(()), Some(seq)) = (&result, tx.seq) {
+            self.last_seq = Some(seq);
+        }
+        if let Some(budget) = self.config.transaction_budget {
+            let elapsed = started.elapsed();
+            if elapsed > budget {
+                warn!(
+                    "transaction '{:?}' took {:?}, exceeding the budget of {:?}",
+                    tx_id, elapsed, budget
+                );
+                self.slow_transactions += 1;
+            }
+        }
+        match result {
+            Ok(()) => self.op_counts.entry(tx.tx_type).or_default().applied += 1,
+            Err(err) => self.reject(tx, err),
+        }
+
+        if self.config.dispute_window > 0 {
+            self.window.push_back((self.clock, client_id, tx_id));
+            self.advance_window();
+        }
+        result
+    }
+
+    // the id a transaction's client is booked under, see `with_client_id_map`
+    fn mapped_client_id(&self, client_id: u16) -> Result<u16, TxError> {
+        match self.config.client_id_map.get(&client_id) {
+            Some(mapped) => Ok(*mapped),
+            None if self.config.reject_unmapped_clients => Err(TxError::UnmappedClient),
+            None => Ok(client_id),
+        }
+    }
+
+    fn reject(&mut self, mut tx: Transaction, err: TxError) {
+        error!("transaction '{:?}' rejected: {}", tx.tx_id, err);
+        self.rejected += 1;
+        self.op_counts.entry(tx.tx_type).or_default().rejected += 1;
+        if err == TxError::UndisputedChargeback {
+            self.undisputed_chargebacks += 1;
+        }
+        self.errors.push(err);
+        if self.config.track_rejects {
+            // as it was read, so it can be processed again
+            if let Some(client_id) = tx.input_client_id.take() {
+                tx.client_id = client_id;
+            }
+            self.rejects.push((tx, err));
+        }
+    }
+
+    fn apply(&mut self, tx: &Transaction) -> Result<(), TxError> {
+        let client = self.clients.get_or_create(tx.client_id);
+        client.last_seen = self.clock;
+        if client.locked {
+            // the account is frozen after a chargeback
+            return Err(TxError::AccountLocked);
+        }
+
+        let referenced = |transactions: &mut dyn TxStore| {
+            let record = transactions
+                .get(tx.tx_id)
+                .ok_or(TxError::UnknownTransaction)?;
+            if record.client_id != tx.client_id {
+                return Err(TxError::ForeignTransaction);
+            }
+            Ok(record)
+        };
+        let amount = match tx.tx_type {
+            Op::Deposit | Op::Withdrawal => {
+                let amount = tx.amount.ok_or(TxError::MissingAmount)?;
+                if amount.is_zero() {
+                    return Err(TxError::ZeroAmount);
+                }
+                if amount.is_sign_negative() {
+                    return Err(TxError::NegativeAmount);
+                }
+                if self.config.reject_on_precision_loss
+                    && amount.round_dp(self.config.precision()) != amount
+                {
+                    return Err(TxError::PrecisionLoss);
+                }
+                if self.transactions.contains(tx.tx_id) {
+                    return Err(TxError::DuplicateTxId);
+                }
+                amount
+            }
+            Op::Dispute if self.disputed.contains_key(&tx.tx_id) => {
+                return Err(TxError::AlreadyDisputed)
+            }
+            Op::Dispute => {
+                let record = referenced(self.transactions.as_mut())?;
+                let original = record.amount;
+                if record.op == Op::Withdrawal {
+                    return Err(TxError::WithdrawalDispute);
+                }
+                if let Some(recency) = self.config.dispute_recency {
+                    if self.clock - record.seq > u64::from(recency) {
+                        return Err(TxError::DisputeTooLate);
+                    }
+                }
+                let held = match (tx.amount, tx.percent) {
+                    (None, None) => Some(original),
+                    (Some(partial), None) => Some(partial),
+                    (None, Some(percent)) => original
+                        .checked_mul(percent)
+                        .and_then(|held| held.checked_div(Decimal::ONE_HUNDRED)),
+                    (Some(_), Some(_)) => None,
+                };
+                match held {
+                    Some(held) if held > Decimal::ZERO && held <= original => held,
+                    _ => return Err(TxError::InvalidDisputeAmount),
+                }
+            }
+            Op::Flag if self.flagged.contains(&tx.tx_id) => return Err(TxError::AlreadyFlagged),
+            Op::Unflag if !self.flagged.contains(&tx.tx_id) => return Err(TxError::NotFlagged),
+            Op::Flag | Op::Unflag => {
+                let record = referenced(self.transactions.as_mut())?;
+                if record.op == Op::Withdrawal {
+                    return Err(TxError::WithdrawalFlag);
+                }
+                record.amount
+            }
+            // the amount held by the dispute of the referenced transaction
+            Op::Resolve | Op::Chargeback => {
+                let undisputed =
+                    if tx.tx_type == Op::Chargeback && self.config.undisputed_chargeback_is_error {
+                        TxError::UndisputedChargeback
+                    } else {
+                        TxError::NotDisputed
+                    };
+                let dispute = self.disputed.get(&tx.tx_id).ok_or(undisputed)?;
+                if dispute.client_id != tx.client_id {
+                    return Err(TxError::ForeignTransaction);
+                }
+                // held may have shrunk since, e.g. through an inconsistent state
+                let mut remaining = client.held - dispute.held;
+                if self.config.round_stored_balances {
+                    remaining = self
+                        .config
+                        .rounding
+                        .apply(remaining, self.config.precision());
+                }
+                if remaining < Decimal::ZERO {
+                    return Err(TxError::InsufficientHeld);
+                }
+                dispute.held
+            }
+        };
+        let amount = &amount;
+
+        // keep amount of transaction that might be referenced to, only once it
+        // got applied so a rejected id can be retried
+        let record = |amount| TxRecord {
+            op: tx.tx_type,
+            client_id: tx.client_id,
+            amount,
+            seq: self.clock,
+        };
+        let before = (client.available, client.held);
+        // amount which actually moved, recorded in the audit log
+        let mut moved = *amount;
+        match tx.tx_type {
+            Op::Deposit => {
+                client.deposit(amount);
+                self.transactions.insert(tx.tx_id, record(*amount));
+            }
+            Op::Withdrawal => {
+                let withdrawn = if client.available < *amount {
+                    if !self.config.partial_withdrawals || client.available <= Decimal::ZERO {
+                        return Err(TxError::InsufficientFunds);
+                    }
+                    warn!(
+                        "withdrawal '{:?}' short by {}, sweeping the available funds of client '{:?}'",
+                        tx.tx_id,
+                        amount - client.available,
+                        client.client_id
+                    );
+                    client.available
+                } else {
+                    *amount
+                };
+                client.withdraw(&withdrawn);
+                moved = withdrawn;
+                self.transactions.insert(tx.tx_id, record(withdrawn));
+            }
+            Op::Dispute => {
+                let held = client.held + amount;
+                if held > client.deposited {
+                    self.violations
+                        .push(InvariantViolation::DisputeExceedsInflows {
+                            client_id: client.client_id,
+                            tx_id: tx.tx_id,
+                            held,
+                            inflows: client.deposited,
+                        });
+                    return Err(TxError::ExceedsInflows);
+                }
+                if self.config.disputes_require_funds && client.available < *amount {
+                    return Err(TxError::InsufficientFunds);
+                }
+                if let Some(ratio) = self.config.max_held_ratio {
+                    if held > client.available * ratio {
+                        return Err(TxError::ExceedsHeldRatio);
+                    }
+                }
+                if self.flagged.contains(&tx.tx_id) {
+                    // the review turned into a formal dispute
+                    let flagged = referenced(self.transactions.as_mut())?.amount;
+                    self.flagged.remove(&tx.tx_id);
+                    client.unflag(&flagged);
+                }
+                let covered = if self.config.cap_uncovered_disputes {
+                    client.available.max(Decimal::ZERO).min(*amount)
+                } else {
+                    *amount
+                };
+                if covered < *amount {
+                    warn!(
+                        "dispute '{:?}' only covered up to {}, {} uncovered",
+                        tx.tx_id,
+                        covered,
+                        amount - covered
+                    );
+                }
+                self.disputed.insert(
+                    tx.tx_id,
+                    Dispute {
+                        client_id: tx.client_id,
+                        held: covered,
+                        uncovered: amount - covered,
+                    },
+                );
+                client.uncovered += amount - covered;
+                moved = covered;
+                client.dispute(&covered);
+                self.config.round_stored(client);
+                self.dispute_effects
+                    .entry(tx.tx_id)
+                    .or_default()
+                    .record(before, client, false);
+            }
+            Op::Flag => {
+                self.flagged.insert(tx.tx_id);
+                client.flag(amount);
+            }
+            Op::Unflag => {
+                self.flagged.remove(&tx.tx_id);
+                client.unflag(amount);
+            }
+            Op::Resolve => {
+                if let Some(dispute) = self.disputed.remove(&tx.tx_id) {
+                    client.uncovered -= dispute.uncovered;
+                }
+                client.resolve(amount);
+                self.config.round_stored(client);
+                self.dispute_effects
+                    .entry(tx.tx_id)
+                    .or_default()
+                    .record(before, client, true);
+            }
+            Op::Chargeback => {
+                if let Some(dispute) = self.disputed.remove(&tx.tx_id) {
+                    client.uncovered -= dispute.uncovered;
+                }
+                client.chargeback(amount, self.clock);
+                self.config.round_stored(client);
+                self.dispute_effects
+                    .entry(tx.tx_id)
+                    .or_default()
+                    .record(before, client, false);
+                let total = client.total();
+                if total < Decimal::ZERO
+                    && self.config.negative_total_policy == NegativeTotalPolicy::ClampZero
+                {
+                    warn!(
+                        "writing off shortfall of {} for client '{:?}'",
+                        -total, client.client_id
+                    );
+                    client.available -= total;
+                }
+            }
+        }
+        self.config.round_stored(client);
+        client.version += 1;
+        if self.config.audit_log {
+            self.audit.push(AuditEntry {
+                op: tx.tx_type,
+                client_id: tx.client_id,
+                tx_id: tx.tx_id,
+                amount: moved,
+                available: client.available,
+                held: client.held,
+            });
+        }
+        Ok(())
+    }
+
+    // evicts transactions which fell out of the dispute window and finalizes
+    // clients without any activity left inside of it
+    fn advance_window(&mut self) {
+        let window = self.config.dispute_window as u64;
+        while let Some(&(seq, client_id, tx_id)) = self.window.front() {
+            if seq + window > self.clock {
+                break;
+            }
+            self.window.pop_front();
+
+            let expired = self
+                .transactions
+                .get(tx_id)
+                .is_some_and(|record| record.seq + window <= self.clock);
+            if expired && !self.disputed.contains_key(&tx_id) && !self.flagged.contains(&tx_id) {
+                self.transactions.remove(tx_id);
+            }
+
+            let idle = self
+                .clients
+                .get(&client_id)
+                .is_some_and(|client| client.last_seen == seq);
+            let open_dispute = self
+                .disputed
+                .values()
+                .any(|dispute| dispute.client_id == client_id);
+            if idle && !open_dispute && (self.on_finalized.is_some() || self.streaming) {
+                self.finalize(client_id);
+            }
+        }
+    }
+
+    fn finalize(&mut self, client_id: u16) {
+        if let Some(client) = self.clients.remove(&client_id) {
+            self.finalized.insert(client_id);
+            let snapshot = client.snapshot(self.clock);
+            if let Some(on_finalized) = self.on_finalized.as_mut() {
+                on_finalized(snapshot);
+            }
+            if self.streaming {
+                self.streamed.push(client);
+            }
+        }
+    }
+
+    /// Checks that the disputes of a resolved transaction left the balances exactly
+    /// as they were.
+    pub fn verify_dispute_neutral(&self, tx_id: u32) -> Result<(), NotNeutral> {
+        match self.dispute_effects.get(&tx_id) {
+            Some(effect) if effect.resolved => {
+                if effect.available.is_zero() && effect.held.is_zero() {
+                    Ok(())
+                } else {
+                    Err(NotNeutral::Residual {
+                        available: effect.available,
+                        held: effect.held,
+                    })
+                }
+            }
+            _ => Err(NotNeutral::NotResolved),
+        }
+    }
+
+    /// Checks the consistency rules over the current state, including disputes
+    /// which were skipped for breaking them.
+    pub fn verify_invariants(&self) -> Result<(), Vec<InvariantViolation>> {
+        let mut violations = self.violations.clone();
+        violations.extend(
+            self.clients
+                .values()
+                .filter(|client| client.held > client.deposited)
+                .map(|client| InvariantViolation::HeldExceedsInflows {
+                    client_id: client.client_id,
+                    held: client.held,
+                    inflows: client.deposited,
+                }),
+        );
+        if let Err(delta) = self.held_matches_disputed() {
+            let disputed: Decimal = self.disputed.values().map(|dispute| dispute.held).sum();
+            violations.push(InvariantViolation::HeldMismatchesDisputed {
+                held: disputed + delta,
+                disputed,
+            });
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// Checks that the held funds of all clients add up to the amounts held by the
+    /// open disputes, returning held minus disputed otherwise. Balances seeded
+    /// through `from_opening_balances` aren't backed by disputes.
+    pub fn held_matches_disputed(&self) -> Result<(), Decimal> {
+        let held: Decimal = self.clients.values().map(|client| client.held).sum();
+        let disputed: Decimal = self.disputed.values().map(|dispute| dispute.held).sum();
+        match held - disputed {
+            delta if delta.is_zero() => Ok(()),
+            delta => Err(delta),
+        }
+    }
+
+    pub fn write_results<T: io::Write>(&mut self, target: T) -> csv::Result<()> {
+        match self.config.output_buffer {
+            Some(capacity) => self.write_all(io::BufWriter::with_capacity(capacity, target)),
+            None => self.write_all(target),
+        }
+    }
+
+    fn write_all<T: io::Write>(&self, target: T) -> csv::Result<()> {
+        let mut writer = WriterBuilder::new().from_writer(target);
+        self.write_rows(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the clients as a JSON array of objects with the fields and the
+    /// formatting of the csv columns, amounts as strings.
+    pub fn write_results_json<T: io::Write>(&self, target: T) -> serde_json::Result<()> {
+        let mut serializer = serde_json::Serializer::new(target);
+        serializer.collect_seq(self.rows())
+    }
+
+    /// Same as `write_results` but never flushes `target`, flushing is left to the caller.
+    pub fn serialize_results<T: io::Write>(&self, target: T) -> csv::Result<()> {
+        let mut writer = WriterBuilder::new().from_writer(NoFlush(target));
+        self.write_rows(&mut writer)?;
+        // only hands the buffered rows over to the target, see `NoFlush`
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the rejected transactions as they were read, with every column
+    /// `transaction_reader` accepts and a `reason` column, for later review and
+    /// reprocessing. Requires reject tracking to be enabled.
+    pub fn write_rejects<T: io::Write>(&self, target: T) -> csv::Result<()> {
+        fn optional(value: Option<impl ToString>) -> String {
+            value.map(|value| value.to_string()).unwrap_or_default()
+        }
+        let mut writer = WriterBuilder::new().from_writer(target);
+        writer.write_record([
+            "type",
+            "client",
+            "tx",
+            "amount",
+            "percent",
+            "seq",
+            "timestamp",
+            "reason",
+        ])?;
+        for (tx, err) in &self.rejects {
+            writer.write_record([
+                tx.tx_type.as_str(),
+                &tx.client_id.to_string(),
+                &tx.tx_id.to_string(),
+                &optional(tx.amount),
+                &optional(tx.percent),
+                &optional(tx.seq),
+                &optional(tx.timestamp),
+                err.as_str(),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes net flow, held and available per client with a `mismatch` column set
+    /// where available isn't net flow minus held. Besides anomalies, that's the
+    /// case for funds taken by a chargeback.
+    pub fn write_reconciliation<T: io::Write>(&self, target: T) -> csv::Result<()> {
+        let mut writer = WriterBuilder::new().from_writer(target);
+        writer.write_record(["client", "net_flow", "held", "available", "mismatch"])?;
+        for client in self.clients.sorted() {
+            let net_flow = client.net_flow();
+            let mismatch = client.available != net_flow - client.held;
+            writer.write_record([
+                &client.client_id.to_string(),
+                &net_flow.to_string(),
+                &client.held.to_string(),
+                &client.available.to_string(),
+                &mismatch.to_string(),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Like `write_results` but a client which fails to serialize is logged and
+    /// skipped instead of aborting the output. Returns the number of skipped
+    /// clients, failing to write to `target` is an error.
+    pub fn write_results_best_effort<T: io::Write>(&self, target: T) -> io::Result<usize> {
+        self.write_best_effort(target, |row| {
+            let mut serialized = Vec::new();
+            let mut writer = WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(&mut serialized);
+            writer.serialize(row)?;
+            writer.flush()?;
+            drop(writer);
+            Ok(serialized)
+        })
+    }
+
+    /// Canonical encoding of the output rows the commitment is built from, i.e.
+    /// the csv rows without header ordered by client id.
+    pub fn commitment_leaves(&self) -> csv::Result<Vec<Vec<u8>>> {
+        self.sorted_rows()
+            .map(|row| {
+                let mut leaf = Vec::new();
+                let mut writer = WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(&mut leaf);
+                writer.serialize(row)?;
+                writer.flush()?;
+                drop(writer);
+                Ok(leaf)
+            })
+            .collect()
+    }
+
+    /// Merkle root over `commitment_leaves` making an export tamper evident,
+    /// see `verify_commitment`.
+    pub fn state_commitment(&self) -> csv::Result<[u8; 32]> {
+        Ok(merkle_root(&self.commitment_leaves()?))
+    }
+
+    // every row is serialized on its own so a failure can't leave a partial row behind
+    fn write_best_effort<T: io::Write>(
+        &self,
+        mut target: T,
+        serialize: impl Fn(&ClientRow) -> csv::Result<Vec<u8>>,
+    ) -> io::Result<usize> {
+        let mut failures = 0;
+        let mut rows = Vec::new();
+        for row in self.rows() {
+            match serialize(&row) {
+                Ok(serialized) => rows.push(serialized),
+                Err(err) => {
+                    error!(
+                        "could not serialize client '{}', will be skipped: {}",
+                        row.client_column(),
+                        err
+                    );
+                    failures += 1;
+                }
+            }
+        }
+
+        let mut columns = vec!["client", "available", "held", "total", "locked"];
+        if self.config.checksum_column {
+            columns.push("checksum");
+        }
+        let mut header = Vec::new();
+        let mut writer = WriterBuilder::new().from_writer(&mut header);
+        writer.write_record(columns)?;
+        writer.flush()?;
+        drop(writer);
+        for row in std::iter::once(&header).chain(rows.iter()) {
+            target.write_all(row)?;
+        }
+        target.flush()?;
+        Ok(failures)
+    }
+
+    /// Writes only the rows which changed since the previous call, the first call
+    /// writes all of them. Tombstones don't change and are written once.
+    pub fn write_results_incremental<T: io::Write>(&mut self, target: T) -> csv::Result<()> {
+        let mut writer = WriterBuilder::new().from_writer(target);
+        let mut emitted_rows = std::mem::take(&mut self.emitted_rows);
+        for client in self.clients.sorted() {
+            if !self.config.reported(client) {
+                continue;
+            }
+            let mut buffer = Vec::new();
+            let mut row = WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(&mut buffer);
+            row.serialize(self.row(client))?;
+            row.flush()?;
+            drop(row);
+            let mut hasher = DefaultHasher::new();
+            buffer.hash(&mut hasher);
+            let hash = hasher.finish();
+            if emitted_rows.get(&client.client_id) != Some(&hash) {
+                writer.serialize(self.row(client))?;
+                emitted_rows.insert(client.client_id, hash);
+            }
+        }
+        self.emitted_rows = emitted_rows;
+        for row in self.tombstone_rows().skip(self.emitted_tombstones) {
+            writer.serialize(row)?;
+        }
+        self.emitted_tombstones = self.tombstones.len();
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes a record per client without delimiters, each field padded to its
+    /// width from `widths`. A value wider than its field is an `InvalidData`
+    /// error naming the field, the rows before it are written.
+    pub fn write_results_fixed_width<T: io::Write>(
+        &self,
+        mut target: T,
+        widths: FieldWidths,
+    ) -> io::Result<()> {
+        for row in self.rows() {
+            let (available, held, total) = row.amounts();
+            let locked = self.config.locked_repr.text(row.client.locked);
+            let record = [
+                // the tombstone placeholder isn't a number
+                fixed_width(
+                    "client",
+                    &row.client_column(),
+                    widths.client,
+                    widths.zero_pad && !row.tombstone,
+                )?,
+                fixed_width("available", &available, widths.available, widths.zero_pad)?,
+                fixed_width("held", &held, widths.held, widths.zero_pad)?,
+                fixed_width("total", &total, widths.total, widths.zero_pad)?,
+                fixed_width("locked", locked, widths.locked, false)?,
+            ];
+            writeln!(target, "{}", record.concat())?;
+        }
+        target.flush()
+    }
+
+    /// Writes each client's row to the writer of its shard, shard `n` covering the
+    /// client ids `n * shard_size..(n + 1) * shard_size`. Writers are requested
+    /// from `make_writer` the first time their shard is needed. Tombstones have
+    /// no id and go to shard 0.
+    pub fn write_results_sharded(
+        &self,
+        shard_size: u32,
+        mut make_writer: impl FnMut(u32) -> Box<dyn io::Write>,
+    ) -> csv::Result<()> {
+        assert!(shard_size > 0, "shard size must be positive");
+        let mut shards: HashMap<u32, csv::Writer<Box<dyn io::Write>>> = HashMap::new();
+        for row in self.sorted_rows() {
+            let shard = match row.tombstone {
+                true => 0,
+                false => u32::from(row.client.client_id) / shard_size,
+            };
+            shards
+                .entry(shard)
+                .or_insert_with(|| WriterBuilder::new().from_writer(make_writer(shard)))
+                .serialize(row)?;
+        }
+        for writer in shards.values_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the rows ordered by client id, tombstones last, into successive
+    /// chunks of at most `rows_per_file` rows, each with its own header. The
+    /// writer of chunk `n` is requested from `make_writer(n)` once the chunk is
+    /// started.
+    pub fn write_results_chunked(
+        &self,
+        rows_per_file: usize,
+        mut make_writer: impl FnMut(usize) -> Box<dyn io::Write>,
+    ) -> csv::Result<()> {
+        assert!(rows_per_file > 0, "rows per file must be positive");
+        let rows: Vec<ClientRow> = self.sorted_rows().collect();
+        for (chunk, rows) in rows.chunks(rows_per_file).enumerate() {
+            let mut writer = WriterBuilder::new().from_writer(make_writer(chunk));
+            for row in rows {
+                writer.serialize(row)?;
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Clients ordered by id as columnar batch with the output's client, available,
+    /// held, total and locked columns, amounts as `Decimal128` with the output precision.
+    /// Tombstones come last with a null client. A precision above `MAX_PRECISION`,
+    /// e.g. of a config built by hand, is an `InvalidArgumentError`.
+    #[cfg(feature = "arrow")]
+    pub fn to_arrow_batch(
+        &self,
+    ) -> Result<arrow::record_batch::RecordBatch, arrow::error::ArrowError> {
+        use arrow::array::{ArrayRef, BooleanArray, Decimal128Array, UInt32Array};
+        use arrow::datatypes::{DataType, Field, Schema};
+        use arrow::error::ArrowError;
+        use std::sync::Arc;
+
+        let precision = self.config.precision();
+        if precision > MAX_PRECISION {
+            return Err(ArrowError::InvalidArgumentError(format!(
+                "precision {} exceeds the maximum of {}",
+                precision, MAX_PRECISION
+            )));
+        }
+        let rows: Vec<ClientRow> = self.sorted_rows().collect();
+        let clients: Vec<&Client> = rows.iter().map(|row| row.client).collect();
+        let amounts = |amount: fn(&Client) -> Decimal| -> Result<ArrayRef, ArrowError> {
+            let values: Vec<i128> = clients
+                .iter()
+                .map(|client| {
+                    let mut amount = self.config.rounding.apply(amount(client), precision);
+                    amount.rescale(precision);
+                    amount.mantissa()
+                })
+                .collect();
+            Ok(Arc::new(
+                Decimal128Array::from(values).with_precision_and_scale(38, precision as i8)?,
+            ))
+        };
+        let decimal = DataType::Decimal128(38, precision as i8);
+        let schema = Schema::new(vec![
+            Field::new("client", DataType::UInt32, true),
+            Field::new("available", decimal.clone(), false),
+            Field::new("held", decimal.clone(), false),
+            Field::new("total", decimal, false),
+            Field::new("locked", DataType::Boolean, false),
+        ]);
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt32Array::from_iter(rows.iter().map(|row| {
+                (!row.tombstone).then_some(u32::from(row.client.client_id))
+            }))),
+            amounts(|client| client.available)?,
+            amounts(|client| client.held)?,
+            amounts(Client::total)?,
+            Arc::new(BooleanArray::from_iter(
+                clients.iter().map(|client| Some(client.locked)),
+            )),
+        ];
+        arrow::record_batch::RecordBatch::try_new(Arc::new(schema), columns)
+    }
+
+    /// Diagnostic dump of all clients, retained transactions and the disputed set as JSON.
+    pub fn dump_debug<T: io::Write>(&self, target: T) -> serde_json::Result<()> {
+        let clients: Vec<serde_json::Value> = self
+            .clients
+            .sorted()
+            .into_iter()
+            .map(|client| {
+                serde_json::json!({
+                    "client": client.client_id,
+                    "available": client.available.to_string(),
+                    "held": client.held.to_string(),
+                    "locked": client.locked,
+                    "locked_at": client.locked_at,
+                    "deposited": client.deposited.to_string(),
+                    "withdrawn": client.withdrawn.to_string(),
+                })
+            })
+            .collect();
+        let transactions: BTreeMap<String, String> = self
+            .transactions
+            .records()
+            .map(|(tx_id, record)| (tx_id.to_string(), record.amount.to_string()))
+            .collect();
+        let mut disputed: Vec<u32> = self.disputed.keys().copied().collect();
+        disputed.sort_unstable();
+
+        let dump = serde_json::json!({
+            "clients": clients,
+            "transactions": transactions,
+            "disputed": disputed,
+            "clock": self.clock,
+        });
+        serde_json::to_writer_pretty(target, &dump)
+    }
+
+    /// Writes the deposits, withdrawals and dispute events of the client with the
+    /// running balances as JSON array, oldest first. Built from the audit log, so
+    /// the timeline is empty unless it is enabled.
+    pub fn write_client_timeline_json<T: io::Write>(
+        &self,
+        client_id: u16,
+        target: T,
+    ) -> serde_json::Result<()> {
+        let events: Vec<serde_json::Value> = self
+            .audit
+            .iter()
+            .filter(|entry| entry.client_id == client_id)
+            .filter(|entry| !matches!(entry.op, Op::Flag | Op::Unflag))
+            .map(|entry| {
+                serde_json::json!({
+                    "type": entry.op.as_str(),
+                    "tx": entry.tx_id,
+                    "amount": entry.amount.to_string(),
+                    "available": entry.available.to_string(),
+                    "held": entry.held.to_string(),
+                    "total": (entry.available + entry.held).to_string(),
+                })
+            })
+            .collect();
+        serde_json::to_writer(target, &events)
+    }
+
+    fn row<'a>(&'a self, client: &'a Client) -> ClientRow<'a> {
+        ClientRow {
+            client,
+            config: &self.config,
+            tombstone: false,
+        }
+    }
+
+    // rows of the full output, tombstones last
+    fn rows(&self) -> impl Iterator<Item = ClientRow<'_>> + '_ {
+        self.reported_clients()
+            .map(|client| self.row(client))
+            .chain(self.tombstone_rows())
+    }
+
+    // same as `rows` ordered by client id
+    fn sorted_rows(&self) -> impl Iterator<Item = ClientRow<'_>> + '_ {
+        self.clients
+            .sorted()
+            .into_iter()
+            .filter(|client| self.config.reported(client))
+            .map(|client| self.row(client))
+            .chain(self.tombstone_rows())
+    }
+
+    fn tombstone_rows(&self) -> impl Iterator<Item = ClientRow<'_>> + '_ {
+        self.tombstones.iter().map(|client| ClientRow {
+            tombstone: true,
+            ..self.row(client)
+        })
+    }
+
+    // clients which are part of the output
+    fn reported_clients(&self) -> impl Iterator<Item = &Client> + '_ {
+        self.clients
+            .values()
+            .filter(|client| self.config.reported(client))
+    }
+
+    fn write_rows<T: io::Write>(&self, writer: &mut csv::Writer<T>) -> csv::Result<()> {
+        self.rows().try_for_each(|row| writer.serialize(row))
+    }
+}
+
+/// Writer adapter swallowing flushes, the csv writer flushes its target
+/// whenever it is flushed or dropped.
+struct NoFlush<T>(T);
+
+impl<T: io::Write> io::Write for NoFlush<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+use rust_decimal_macros::dec;
+
+/// Logger keeping every formatted record so tests can assert on log output.
+#[cfg(test)]
+struct CaptureLogger;
+
+#[cfg(test)]
+static CAPTURED_LOGS: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+#[cfg(test)]
+impl log::Log for CaptureLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        CAPTURED_LOGS
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", record.level(), record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+fn capture_logs() {
+    static LOGGER: CaptureLogger = CaptureLogger;
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log::LevelFilter::Trace);
+    }
+}
+
+#[cfg(test)]
+fn captured_logs() -> Vec<String> {
+    CAPTURED_LOGS.lock().unwrap().clone()
+}
+
+#[test]
+fn sanity() {
+    let mut bursar = Bursar::new();
+    let client_id = 1;
+
+    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 1, Some(dec!(20))));
+    bursar.process_transaction(Transaction::new(
+        Op::Withdrawal,
+        client_id,
+        2,
+        Some(dec!(10)),
+    ));
+
+    let client = bursar.clients.get(&client_id);
+    assert!(client.is_some());
+    let client = client.unwrap();
+    assert_eq!(client.total(), dec!(10));
+    assert_eq!(client.available, dec!(10));
+    assert_eq!(client.held, dec!(0));
+    assert!(!client.locked);
+}
+
+#[test]
+fn basic_dispute() {
+    let mut bursar = Bursar::new();
+    let client_id = 1;
+
+    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 2, Some(dec!(42))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, client_id, 1, None));
+
+    let client = bursar.clients.get(&client_id);
+    assert!(client.is_some());
+    let client = client.unwrap();
+    assert_eq!(client.total(), dec!(52));
+    assert_eq!(client.available, dec!(42));
+    assert_eq!(client.held, dec!(10));
+    assert!(!client.locked);
+}
+
+#[test]
+fn resolve_dispute() {
+    let mut bursar = Bursar::new();
+    let client_id = 1;
+
+    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 2, Some(dec!(42))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, client_id, 1, None));
+    bursar.process_transaction(Transaction::new(Op::Resolve, client_id, 1, None));
+
+    let client = bursar.clients.get(&client_id);
+    assert!(client.is_some());
+    let client = client.unwrap();
+    assert_eq!(client.total(), dec!(52));
+    assert_eq!(client.available, dec!(52));
+    assert_eq!(client.held, dec!(0));
+    assert!(!client.locked);
+}
+
+#[test]
+fn chargeback_dispute() {
+    let mut bursar = Bursar::new();
+    let client_id = 1;
+
+    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 2, Some(dec!(42))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, client_id, 1, None));
+    bursar.process_transaction(Transaction::new(Op::Chargeback, client_id, 1, None));
+
+    let client = bursar.clients.get(&client_id);
+    assert!(client.is_some());
+    let client = client.unwrap();
+    assert_eq!(client.total(), dec!(42));
+    assert_eq!(client.available, dec!(42));
+    assert_eq!(client.held, dec!(0));
+    assert!(client.locked);
+}
+
+#[test]
+fn false_resolve() {
+    let mut bursar = Bursar::new();
+    let client_id = 1;
+
+    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 1, Some(dec!(10))));
     bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 2, Some(dec!(42))));
+    bursar.process_transaction(Transaction::new(Op::Resolve, client_id, 1, None));
+
+    let client = bursar.clients.get(&client_id);
+    assert!(client.is_some());
+    let client = client.unwrap();
+    assert_eq!(client.total(), dec!(52));
+    assert_eq!(client.available, dec!(52));
+    assert_eq!(client.held, dec!(0));
+    assert!(!client.locked);
+}
+
+#[test]
+fn locked_summary() {
+    capture_logs();
+    let mut bursar = Bursar::new().with_locked_summary(true);
+
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 7, 1, Some(dec!(10))),
+            Transaction::new(Op::Deposit, 8, 2, Some(dec!(10))),
+            Transaction::new(Op::Dispute, 7, 1, None),
+            Transaction::new(Op::Chargeback, 7, 1, None),
+        ]
+        .into_iter(),
+    );
+
+    assert!(captured_logs()
+        .iter()
+        .any(|line| line == "INFO locked accounts: 1 [7]"));
+}
+
+#[test]
+fn accounting_amount_parentheses() {
+    #[derive(Deserialize)]
+    struct Row {
+        #[serde(deserialize_with = "accounting_amount")]
+        amount: Option<Decimal>,
+    }
+
+    let data = "amount\n(10.00)\n10.00\n\n";
+    let mut reader = csv::ReaderBuilder::new().from_reader(data.as_bytes());
+    let amounts: Vec<Option<Decimal>> = reader
+        .deserialize::<Row>()
+        .map(|row| row.unwrap().amount)
+        .collect();
+
+    assert_eq!(amounts, vec![Some(dec!(-10.00)), Some(dec!(10.00))]);
+}
+
+#[test]
+fn ticks_since_locked() {
+    let mut bursar = Bursar::new();
+
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 1, 1, None));
+    bursar.process_transaction(Transaction::new(Op::Chargeback, 1, 1, None));
+    assert_eq!(
+        bursar.client_balance(1).unwrap().ticks_since_locked,
+        Some(0)
+    );
+
+    bursar.process_transaction(Transaction::new(Op::Deposit, 2, 2, Some(dec!(5))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 2, 3, Some(dec!(5))));
+
+    let snapshot = bursar.client_balance(1).unwrap();
+    assert!(snapshot.locked);
+    assert_eq!(snapshot.ticks_since_locked, Some(2));
+    assert_eq!(bursar.client_balance(2).unwrap().ticks_since_locked, None);
+}
+
+#[test]
+fn serialize_results_does_not_flush() {
+    struct CountingFlush {
+        buffer: Vec<u8>,
+        flushes: usize,
+    }
+
+    impl io::Write for CountingFlush {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.buffer.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(1))));
+
+    let mut target = CountingFlush {
+        buffer: Vec::new(),
+        flushes: 0,
+    };
+    bursar.serialize_results(&mut target).unwrap();
+
+    assert_eq!(target.flushes, 0);
+    assert_eq!(
+        target.buffer,
+        b"client,available,held,total,locked\n1,1,0.0000,1,false\n"
+    );
+}
+
+#[test]
+fn dispute_exceeding_inflows_is_flagged() {
+    let mut bursar = Bursar::new();
+    let client_id = 1;
+
+    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 2, Some(dec!(5))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, client_id, 1, None));
+    assert_eq!(bursar.verify_invariants(), Ok(()));
+
+    // only disputes of deposits get this far, lose track of an inflow
+    bursar.clients.get_mut(&client_id).unwrap().deposited = dec!(10);
+    bursar.process_transaction(Transaction::new(Op::Dispute, client_id, 2, None));
+
+    assert_eq!(
+        bursar.verify_invariants(),
+        Err(vec![InvariantViolation::DisputeExceedsInflows {
+            client_id,
+            tx_id: 2,
+            held: dec!(15),
+            inflows: dec!(10),
+        }])
+    );
+    let client = bursar.clients.get(&client_id).unwrap();
+    assert_eq!(client.held, dec!(10));
+    assert_eq!(client.available, dec!(5));
+    assert!(!bursar.disputed.contains_key(&2));
+}
+
+#[test]
+fn sharded_results() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    type Outputs = Rc<RefCell<HashMap<u32, Vec<u8>>>>;
+
+    struct ShardBuffer(u32, Outputs);
+
+    impl io::Write for ShardBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.1.borrow_mut().entry(self.0).or_default().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(1))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 12, 2, Some(dec!(2))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 3, 3, Some(dec!(3))));
+
+    let outputs = Outputs::default();
+    let shard_outputs = Rc::clone(&outputs);
+    bursar
+        .write_results_sharded(10, move |shard| {
+            Box::new(ShardBuffer(shard, Rc::clone(&shard_outputs)))
+        })
+        .unwrap();
+
+    let outputs = outputs.borrow();
+    assert_eq!(outputs.len(), 2);
+    assert_eq!(
+        String::from_utf8(outputs[&0].clone()).unwrap(),
+        "client,available,held,total,locked\n1,1,0.0000,1,false\n3,3,0.0000,3,false\n"
+    );
+    assert_eq!(
+        String::from_utf8(outputs[&1].clone()).unwrap(),
+        "client,available,held,total,locked\n12,2,0.0000,2,false\n"
+    );
+}
+
+#[test]
+fn net_flow_ignores_disputes() {
+    let mut bursar = Bursar::new();
+    let client_id = 1;
+
+    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 1, Some(dec!(100))));
+    bursar.process_transaction(Transaction::new(
+        Op::Withdrawal,
+        client_id,
+        2,
+        Some(dec!(30)),
+    ));
+    assert_eq!(bursar.net_flow(client_id), Some(dec!(70)));
+
+    bursar.process_transaction(Transaction::new(Op::Dispute, client_id, 1, None));
+
+    assert_eq!(bursar.client_balance(client_id).unwrap().held, dec!(100));
+    assert_eq!(bursar.net_flow(client_id), Some(dec!(70)));
+    assert_eq!(bursar.net_flow(2), None);
+}
+
+#[test]
+fn resolve_before_dispute_within_window() {
+    let transactions = || {
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(Op::Resolve, 1, 1, None),
+            Transaction::new(Op::Deposit, 1, 2, Some(dec!(5))),
+            Transaction::new(Op::Dispute, 1, 1, None),
+        ]
+        .into_iter()
+    };
+
+    let mut bursar = Bursar::new().with_reorder_window(2);
+    bursar.consume(transactions());
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!(client.available, dec!(15));
+    assert_eq!(client.held, dec!(0));
+
+    // the dispute arrives after the resolve expired
+    let mut bursar = Bursar::new().with_reorder_window(1);
+    bursar.consume(transactions());
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!(client.available, dec!(5));
+    assert_eq!(client.held, dec!(10));
+}
+
+#[test]
+fn debug_dump() {
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 2, Some(dec!(2.5))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 1, 2, None));
+
+    let mut output = Vec::new();
+    bursar.dump_debug(&mut output).unwrap();
+    let dump: serde_json::Value = serde_json::from_slice(&output).unwrap();
+
+    assert_eq!(dump["transactions"]["1"], "10");
+    assert_eq!(dump["disputed"], serde_json::json!([2]));
+    assert_eq!(dump["clients"][0]["held"], "2.5");
+}
+
+#[test]
+fn negative_total_policies() {
+    let transactions = || {
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(100))),
+            Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(80))),
+            Transaction::new(Op::Dispute, 1, 1, None),
+            Transaction::new(Op::Chargeback, 1, 1, None),
+        ]
+        .into_iter()
+    };
+
+    let mut bursar = Bursar::new();
+    bursar.consume(transactions());
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!(client.total, dec!(-80));
+    assert_eq!(client.available, dec!(-80));
+    assert!(client.locked);
+
+    let mut bursar = Bursar::new().with_negative_total_policy(NegativeTotalPolicy::ClampZero);
+    bursar.consume(transactions());
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!(client.total, dec!(0));
+    assert_eq!(client.available, dec!(0));
+    assert_eq!(client.held, dec!(0));
+    assert!(client.locked);
+}
+
+#[test]
+fn custom_headers() {
+    let header_map: HashMap<String, Field> = [
+        ("transaction_type", Field::Type),
+        ("Customer_ID", Field::Client),
+        ("transaction_id", Field::Tx),
+        ("value", Field::Amount),
+    ]
+    .into_iter()
+    .map(|(name, field)| (name.to_string(), field))
+    .collect();
+    let data =
+        "Transaction_Type,customer_id,TRANSACTION_ID,Value\ndeposit,1,1,2.5\nwithdrawal,1,2,1.0\n";
+
+    let mut reader = transaction_reader(data.as_bytes(), &header_map).unwrap();
+    let transactions: Vec<Transaction> = reader
+        .deserialize::<Transaction>()
+        .map(|tx| tx.unwrap())
+        .collect();
+    let mut bursar = Bursar::new();
+    bursar.consume(transactions.into_iter());
+
+    assert_eq!(bursar.client_balance(1).unwrap().available, dec!(1.5));
+}
+
+#[test]
+fn client_maps_produce_identical_output() {
+    let results = |kind: ClientMapKind| {
+        let mut bursar = Bursar::new().with_client_map(kind);
+        bursar.consume((0..200u32).map(|tx_id| {
+            let client_id = (tx_id * 37 % 50) as u16;
+            match tx_id % 4 {
+                3 => Transaction::new(Op::Dispute, client_id, tx_id - 3, None),
+                2 => Transaction::new(Op::Withdrawal, client_id, tx_id, Some(dec!(1.5))),
+                _ => Transaction::new(Op::Deposit, client_id, tx_id, Some(dec!(2.25))),
+            }
+        }));
+        let mut output = Vec::new();
+        bursar.dump_debug(&mut output).unwrap();
+        output
+    };
+
+    assert_eq!(results(ClientMapKind::Hash), results(ClientMapKind::BTree));
+}
+
+#[test]
+fn dispute_batch_is_atomic() {
+    let mut bursar = Bursar::new();
+    let client_id = 1;
+    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 2, Some(dec!(20))));
+
+    assert_eq!(
+        bursar.dispute_batch(client_id, &[1, 9, 2]),
+        Err(BatchDisputeError::UnknownTransaction(9))
+    );
+    let client = bursar.client_balance(client_id).unwrap();
+    assert_eq!(client.available, dec!(30));
+    assert_eq!(client.held, dec!(0));
+    assert!(bursar.disputed.is_empty());
+
+    assert_eq!(
+        bursar.dispute_batch(client_id, &[1, 1]),
+        Err(BatchDisputeError::AlreadyDisputed(1))
+    );
+    assert_eq!(bursar.dispute_batch(client_id, &[1, 2]), Ok(()));
+    let client = bursar.client_balance(client_id).unwrap();
+    assert_eq!(client.available, dec!(0));
+    assert_eq!(client.held, dec!(30));
+}
+
+#[test]
+fn dispute_batch_undoes_rejected_disputes() {
+    let deposits = |bursar: &mut Bursar| {
+        bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))));
+        bursar.process_transaction(Transaction::new(Op::Deposit, 1, 2, Some(dec!(20))));
+        bursar.process_transaction(Transaction::new(Op::Withdrawal, 1, 3, Some(dec!(15))));
+    };
+
+    // the second dispute lacks the funds after the first one held 10 of 15
+    let mut bursar = Bursar::new().with_disputes_requiring_funds(true);
+    deposits(&mut bursar);
+    let stats = bursar.stats();
+    assert_eq!(
+        bursar.dispute_batch(1, &[1, 2]),
+        Err(BatchDisputeError::Rejected(2, TxError::InsufficientFunds))
+    );
+    assert_eq!(bursar.exact_balances(1), Some((dec!(15), dec!(0))));
+    assert!(bursar.disputed.is_empty());
+    assert_eq!(bursar.stats(), stats);
+    assert!(bursar.errors().is_empty());
+
+    let mut bursar = Bursar::new();
+    deposits(&mut bursar);
+    bursar.clients.get_mut(&1).unwrap().locked = true;
+    assert_eq!(
+        bursar.dispute_batch(1, &[1]),
+        Err(BatchDisputeError::Rejected(1, TxError::AccountLocked))
+    );
+    assert_eq!(bursar.exact_balances(1), Some((dec!(15), dec!(0))));
+}
+
+#[test]
+fn rounding_negative_balances() {
+    let results = |rounding: Rounding| {
+        let mut bursar = Bursar::new().with_rounding(rounding);
+        bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(1))));
+        bursar.process_transaction(Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(0.12345))));
+        bursar.process_transaction(Transaction::new(Op::Dispute, 1, 1, None));
+        let mut output = Vec::new();
+        bursar.write_results(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    assert_eq!(
+        results(Rounding::Bankers),
+        "client,available,held,total,locked\n1,-0.1234,1,0.8766,false\n"
+    );
+    assert_eq!(
+        results(Rounding::HalfAwayFromZero),
+        "client,available,held,total,locked\n1,-0.1235,1,0.8766,false\n"
+    );
+}
+
+#[test]
+fn locked_repr_one_zero() {
+    let mut bursar = Bursar::new()
+        .with_locked_repr(LockedRepr::OneZero)
+        .with_client_map(ClientMapKind::BTree);
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(5))),
+            Transaction::new(Op::Deposit, 2, 2, Some(dec!(5))),
+            Transaction::new(Op::Dispute, 2, 2, None),
+            Transaction::new(Op::Chargeback, 2, 2, None),
+        ]
+        .into_iter(),
+    );
+
+    let mut output = Vec::new();
+    bursar.write_results(&mut output).unwrap();
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n1,5,0.0000,5,0\n2,0.0000,0.0000,0.0000,1\n"
+    );
+}
+
+#[test]
+fn dispute_activity_counts() {
+    let mut bursar = Bursar::new();
+    let client_id = 1;
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, client_id, 1, Some(dec!(10))),
+            Transaction::new(Op::Deposit, client_id, 2, Some(dec!(20))),
+            Transaction::new(Op::Dispute, client_id, 1, None),
+            Transaction::new(Op::Resolve, client_id, 1, None),
+            Transaction::new(Op::Dispute, client_id, 2, None),
+            Transaction::new(Op::Chargeback, client_id, 2, None),
+        ]
+        .into_iter(),
+    );
+
+    assert_eq!(bursar.dispute_activity(client_id), Some((2, 1, 1)));
+    assert_eq!(bursar.dispute_activity(2), None);
+}
+
+#[test]
+fn client_finalized_in_dispute_window() {
+    use std::sync::{Arc, Mutex};
+
+    let finalized = Arc::new(Mutex::new(Vec::new()));
+    let on_finalized = Arc::clone(&finalized);
+    let mut bursar = Bursar::new()
+        .with_dispute_window(2)
+        .on_client_finalized(move |snapshot| on_finalized.lock().unwrap().push(snapshot));
+
+    let seen_by_then = Arc::clone(&finalized);
+    let transactions = vec![
+        Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))),
+        Transaction::new(Op::Deposit, 2, 2, Some(dec!(5))),
+        Transaction::new(Op::Deposit, 2, 3, Some(dec!(5))),
+    ]
+    .into_iter()
+    .chain(std::iter::once_with(move || {
+        // client 1 had no activity for two transactions
+        let finalized = seen_by_then.lock().unwrap();
+        assert_eq!(finalized.len(), 1);
+        assert_eq!(finalized[0].client_id, 1);
+        assert_eq!(finalized[0].available, dec!(10));
+        Transaction::new(Op::Dispute, 1, 1, None)
+    }));
+    bursar.consume(transactions);
+
+    assert_eq!(finalized.lock().unwrap().len(), 1);
+    assert!(bursar.client_balance(1).is_none());
+    assert!(!bursar.transactions.contains(1));
+    assert_eq!(bursar.client_balance(2).unwrap().available, dec!(10));
+}
+
+#[test]
+fn transaction_accessors() {
+    let tx = Transaction::new(Op::Withdrawal, 3, 42, Some(dec!(1.5)));
+
+    assert_eq!(tx.op(), &Op::Withdrawal);
+    assert_eq!(tx.client_id(), 3);
+    assert_eq!(tx.tx_id(), 42);
+    assert_eq!(tx.amount(), Some(dec!(1.5)));
+    assert_eq!(Transaction::new(Op::Dispute, 3, 42, None).amount(), None);
+}
+
+#[test]
+fn flag_then_dispute() {
+    let mut bursar = Bursar::new();
+    let client_id = 1;
+    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 2, Some(dec!(4))));
+
+    bursar.process_transaction(Transaction::new(Op::Flag, client_id, 1, None));
+    bursar.process_transaction(Transaction::new(Op::Flag, client_id, 2, None));
+    let client = bursar.client_balance(client_id).unwrap();
+    assert_eq!(client.pending, dec!(14));
+    assert_eq!(client.available, dec!(14));
+    assert_eq!(client.held, dec!(0));
+
+    bursar.process_transaction(Transaction::new(Op::Unflag, client_id, 2, None));
+    bursar.process_transaction(Transaction::new(Op::Unflag, client_id, 2, None));
+    assert_eq!(bursar.client_balance(client_id).unwrap().pending, dec!(10));
+
+    bursar.process_transaction(Transaction::new(Op::Dispute, client_id, 1, None));
+    let client = bursar.client_balance(client_id).unwrap();
+    assert_eq!(client.pending, dec!(0));
+    assert_eq!(client.available, dec!(4));
+    assert_eq!(client.held, dec!(10));
+    assert_eq!(client.total, dec!(14));
+
+    bursar.process_transaction(Transaction::new(
+        Op::Withdrawal,
+        client_id,
+        3,
+        Some(dec!(1)),
+    ));
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Flag, client_id, 3, None)),
+        Err(TxError::WithdrawalFlag)
+    );
+    assert_eq!(bursar.client_balance(client_id).unwrap().pending, dec!(0));
+}
+
+#[test]
+fn incremental_results() {
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(1))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 2, 2, Some(dec!(2))));
+
+    let mut output = Vec::new();
+    bursar.write_results_incremental(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n1,1,0.0000,1,false\n2,2,0.0000,2,false\n"
+    );
+
+    bursar.process_transaction(Transaction::new(Op::Deposit, 2, 3, Some(dec!(3))));
+    let mut output = Vec::new();
+    bursar.write_results_incremental(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n2,5,0.0000,5,false\n"
+    );
+
+    let mut output = Vec::new();
+    bursar.write_results_incremental(&mut output).unwrap();
+    assert!(output.is_empty());
+}
+
+#[test]
+fn dispute_resolve_is_neutral() {
+    let mut bursar = Bursar::new();
+    let client_id = 1;
+    let amount = dec!(0.1234567890123456789);
+    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 1, Some(amount)));
+    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 2, Some(dec!(3))));
     bursar.process_transaction(Transaction::new(Op::Dispute, client_id, 1, None));
+    assert_eq!(
+        bursar.verify_dispute_neutral(1),
+        Err(NotNeutral::NotResolved)
+    );
+
+    bursar.process_transaction(Transaction::new(Op::Resolve, client_id, 1, None));
+
+    assert_eq!(bursar.verify_dispute_neutral(1), Ok(()));
+    assert_eq!(
+        bursar.verify_dispute_neutral(2),
+        Err(NotNeutral::NotResolved)
+    );
+    let client = bursar.client_balance(client_id).unwrap();
+    assert_eq!(client.available, amount + dec!(3));
+    assert_eq!(client.held, dec!(0));
+}
+
+#[test]
+fn best_effort_skips_failing_client() {
+    let mut bursar = Bursar::new().with_client_map(ClientMapKind::BTree);
+    for client_id in 1..=3 {
+        bursar.process_transaction(Transaction::new(
+            Op::Deposit,
+            client_id,
+            client_id.into(),
+            Some(dec!(1)),
+        ));
+    }
+
+    let mut output = Vec::new();
+    let failures = bursar.write_best_effort(&mut output, |row| {
+        if row.client.client_id == 2 {
+            return Err(io::Error::other("mocked failure").into());
+        }
+        let mut writer = WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(Vec::new());
+        writer.serialize(row)?;
+        Ok(writer.into_inner().unwrap())
+    });
+
+    assert_eq!(failures.unwrap(), 1);
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n1,1,0.0000,1,false\n3,1,0.0000,1,false\n"
+    );
+
+    let mut output = Vec::new();
+    assert_eq!(bursar.write_results_best_effort(&mut output).unwrap(), 0);
+    assert_eq!(String::from_utf8(output).unwrap().lines().count(), 4);
+
+    let err = bursar
+        .write_results_best_effort(FailingWriter { limit: 40 })
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+}
+
+#[test]
+fn blank_operation_type() {
+    let data = "type,client,tx,amount\n   ,1,1,1.0\nwithdraw,1,2,1.0\ndeposit,1,3,1.0\n";
+    let mut reader = csv::Reader::from_reader(data.as_bytes());
+    let results: Vec<Result<Transaction, String>> = reader
+        .deserialize::<Transaction>()
+        .map(|tx| tx.map_err(|err| err.to_string()))
+        .collect();
+
+    assert!(results[0]
+        .as_ref()
+        .unwrap_err()
+        .ends_with("missing operation type"));
+    assert!(results[1]
+        .as_ref()
+        .unwrap_err()
+        .ends_with("unknown operation type 'withdraw'"));
+    assert_eq!(results[2].as_ref().unwrap().op(), &Op::Deposit);
+}
+
+#[test]
+fn rejects_written_with_reason() {
+    let mut bursar = Bursar::new().with_reject_tracking(true);
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(10.5))));
+    bursar.process_transaction(Transaction::new(Op::Resolve, 1, 1, None));
+
+    assert_eq!(bursar.client_balance(1).unwrap().available, dec!(10));
+    let mut output = Vec::new();
+    bursar.write_rejects(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "type,client,tx,amount,percent,seq,timestamp,reason\n\
+         withdrawal,1,2,10.5,,,,insufficient_funds\n\
+         resolve,1,1,,,,,not_disputed\n"
+    );
+
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Withdrawal, 1, 1, Some(dec!(1))));
+    assert!(bursar.rejects().is_empty());
+}
+
+#[test]
+fn rejects_can_be_reprocessed() {
+    let client_id_map = HashMap::from([(1, 1000)]);
+    let mut bursar = Bursar::new()
+        .with_client_id_map(client_id_map.clone())
+        .with_reject_tracking(true);
+    bursar.process_transaction(Transaction::new(Op::Withdrawal, 1, 1, Some(dec!(5))));
+    bursar.process_transaction(
+        Transaction::new(Op::Dispute, 1, 2, None)
+            .with_percent(dec!(50))
+            .with_seq(7)
+            .with_timestamp(100),
+    );
+    let mut output = Vec::new();
+    bursar.write_rejects(&mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(
+        output,
+        "type,client,tx,amount,percent,seq,timestamp,reason\n\
+         withdrawal,1,1,5,,,,insufficient_funds\n\
+         dispute,1,2,,50,7,100,unknown_transaction\n"
+    );
+
+    // once the deposit arrived the rejects go through as the original ones
+    let mut retry = Bursar::new().with_client_id_map(client_id_map);
+    retry.process_transaction(Transaction::new(Op::Deposit, 1, 2, Some(dec!(10))));
+    let mut reader = transaction_reader(output.as_bytes(), &HashMap::new()).unwrap();
+    retry.consume(read_transactions(&mut reader));
+    assert_eq!(retry.exact_balances(1000), Some((dec!(0), dec!(5))));
+    assert_eq!(retry.last_processed_seq(), Some(7));
+}
+
+#[test]
+fn zero_amounts() {
+    let mut bursar = Bursar::new().with_reject_tracking(true);
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(0.0000))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 1, 1, None));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 2, Some(dec!(5))));
+    bursar.process_transaction(Transaction::new(Op::Withdrawal, 1, 3, Some(dec!(0))));
+
+    let reasons: Vec<TxError> = bursar.rejects().iter().map(|(_, err)| *err).collect();
+    assert_eq!(
+        reasons,
+        vec![
+            TxError::ZeroAmount,
+            TxError::UnknownTransaction,
+            TxError::ZeroAmount
+        ]
+    );
+    assert_eq!(bursar.client_balance(1).unwrap().available, dec!(5));
+}
+
+#[test]
+fn negative_deposit() {
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(5))));
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Deposit, 1, 2, Some(dec!(-3)))),
+        Err(TxError::NegativeAmount)
+    );
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Withdrawal, 1, 3, Some(dec!(-3)))),
+        Err(TxError::NegativeAmount)
+    );
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!((client.available, client.total), (dec!(5), dec!(5)));
+    assert_eq!(bursar.net_flow(1), Some(dec!(5)));
+}
+
+#[test]
+fn snapshots_every_two_transactions() {
+    let mut bursar = Bursar::new();
+    let mut snapshots = Vec::new();
+    bursar.consume_with_snapshots(
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(Op::Deposit, 2, 2, Some(dec!(5))),
+            Transaction::new(Op::Withdrawal, 2, 3, Some(dec!(50))),
+            Transaction::new(Op::Dispute, 1, 1, None),
+        ]
+        .into_iter(),
+        2,
+        |stats| snapshots.push(stats),
+    );
+
+    assert_eq!(
+        snapshots,
+        vec![
+            BursarStats {
+                processed: 2,
+                rejected: 0,
+                undisputed_chargebacks: 0,
+                slow_transactions: 0,
+                shadow_rejected: 0,
+                clients: 2,
+                locked: 0,
+                available: dec!(15),
+                held: dec!(0),
+            },
+            BursarStats {
+                processed: 4,
+                rejected: 1,
+                undisputed_chargebacks: 0,
+                slow_transactions: 0,
+                shadow_rejected: 0,
+                clients: 2,
+                locked: 0,
+                available: dec!(5),
+                held: dec!(10),
+            },
+        ]
+    );
+}
+
+#[test]
+fn dispute_requiring_funds() {
+    let transactions = || {
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(100))),
+            Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(80))),
+            Transaction::new(Op::Dispute, 1, 1, None),
+        ]
+        .into_iter()
+    };
+
+    let mut bursar = Bursar::new()
+        .with_disputes_requiring_funds(true)
+        .with_reject_tracking(true);
+    bursar.consume(transactions());
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!(client.available, dec!(20));
+    assert_eq!(client.held, dec!(0));
+    assert_eq!(bursar.rejects()[0].1, TxError::InsufficientFunds);
+
+    let mut bursar = Bursar::new();
+    bursar.consume(transactions());
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!(client.available, dec!(-80));
+    assert_eq!(client.held, dec!(100));
+}
+
+#[test]
+fn retained_transactions() {
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 2, 2, Some(dec!(2.5))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 1, 1, None));
+
+    let mut retained: Vec<(u32, Decimal)> = bursar.retained_transactions().collect();
+    retained.sort_unstable_by_key(|(tx_id, _)| *tx_id);
+    assert_eq!(retained, vec![(1, dec!(10)), (2, dec!(2.5))]);
+}
+
+#[test]
+fn process_if_version() {
+    let mut bursar = Bursar::new();
+    let deposit = |tx_id| Transaction::new(Op::Deposit, 1, tx_id, Some(dec!(1)));
+
+    assert_eq!(bursar.process_if_version(deposit(1), 0), Ok(()));
+    assert_eq!(bursar.process_if_version(deposit(2), 1), Ok(()));
+    assert_eq!(
+        bursar.process_if_version(deposit(3), 1),
+        Err(VersionMismatch {
+            expected: 1,
+            actual: 2
+        })
+    );
+
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!(client.version, 2);
+    assert_eq!(client.available, dec!(2));
+}
+
+#[test]
+fn batch_helpers_with_client_id_map() {
+    let bursar = || {
+        let mut bursar = Bursar::new().with_client_id_map(HashMap::from([(1, 1000)]));
+        bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))));
+        bursar
+    };
+
+    let mut mapped = bursar();
+    assert_eq!(mapped.dispute_batch(1, &[1]), Ok(()));
+    assert_eq!(mapped.exact_balances(1000), Some((dec!(0), dec!(10))));
+    assert_eq!(mapped.resolve_all(1), 1);
+    assert_eq!(mapped.exact_balances(1000), Some((dec!(10), dec!(0))));
+
+    // without rejecting unmapped clients the mapped id stands for itself
+    let mut mapped = bursar();
+    assert_eq!(mapped.dispute_batch(1000, &[1]), Ok(()));
+    assert_eq!(mapped.resolve_all(1000), 1);
+
+    let mut rejecting = bursar().with_unmapped_clients_rejected(true);
+    assert_eq!(
+        rejecting.dispute_batch(1000, &[1]),
+        Err(BatchDisputeError::UnmappedClient)
+    );
+    assert_eq!(rejecting.dispute_batch(1, &[1]), Ok(()));
+    assert_eq!(rejecting.resolve_all(1000), 0);
+    assert_eq!(rejecting.resolve_all(1), 1);
+    assert_eq!(rejecting.exact_balances(1000), Some((dec!(10), dec!(0))));
+}
+
+#[test]
+fn process_if_version_with_client_id_map() {
+    let mut bursar = Bursar::new().with_client_id_map(HashMap::from([(1, 1000)]));
+    let deposit = |tx_id| Transaction::new(Op::Deposit, 1, tx_id, Some(dec!(1)));
+
+    assert_eq!(bursar.process_if_version(deposit(1), 0), Ok(()));
+    // the version is the one of the mapped client
+    assert_eq!(bursar.process_if_version(deposit(2), 1), Ok(()));
+    assert_eq!(
+        bursar.process_if_version(deposit(3), 0),
+        Err(VersionMismatch {
+            expected: 0,
+            actual: 2
+        })
+    );
+    assert!(bursar.client_balance(1).is_none());
+    assert_eq!(bursar.client_balance(1000).unwrap().available, dec!(2));
+}
+
+#[test]
+fn partial_disputes() {
+    let mut bursar = Bursar::new();
+    let client_id = 1;
+    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 1, Some(dec!(100))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 2, Some(dec!(30))));
+
+    bursar.process_transaction(
+        Transaction::new(Op::Dispute, client_id, 1, None).with_percent(dec!(50)),
+    );
+    let client = bursar.client_balance(client_id).unwrap();
+    assert_eq!(client.held, dec!(50));
+    assert_eq!(client.available, dec!(80));
+
+    bursar.process_transaction(Transaction::new(
+        Op::Dispute,
+        client_id,
+        2,
+        Some(dec!(12.5)),
+    ));
+    assert_eq!(bursar.client_balance(client_id).unwrap().held, dec!(62.5));
+
+    bursar.process_transaction(Transaction::new(Op::Resolve, client_id, 1, None));
+    bursar.process_transaction(Transaction::new(Op::Chargeback, client_id, 2, None));
+    let client = bursar.client_balance(client_id).unwrap();
+    assert_eq!(client.held, dec!(0));
+    assert_eq!(client.available, dec!(117.5));
+}
+
+#[test]
+fn invalid_partial_disputes() {
+    let mut bursar = Bursar::new().with_reject_tracking(true);
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(100))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 1, 1, Some(dec!(100.01))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 1, 1, None).with_percent(dec!(0)));
+    bursar.process_transaction(
+        Transaction::new(Op::Dispute, 1, 1, Some(dec!(1))).with_percent(dec!(1)),
+    );
+
+    assert_eq!(bursar.rejects().len(), 3);
+    assert!(bursar
+        .rejects()
+        .iter()
+        .all(|(_, err)| *err == TxError::InvalidDisputeAmount));
+    assert_eq!(bursar.client_balance(1).unwrap().held, dec!(0));
+}
+
+#[test]
+fn percent_column() {
+    let data = "type,client,tx,amount,percent\ndeposit,1,1,100,\ndispute,1,1,,12.5\n";
+    let mut reader = csv::Reader::from_reader(data.as_bytes());
+    let transactions: Vec<Transaction> = reader.deserialize().map(|tx| tx.unwrap()).collect();
+    assert_eq!(transactions[0].percent(), None);
+    assert_eq!(transactions[1].percent(), Some(dec!(12.5)));
+
+    let mut bursar = Bursar::new();
+    bursar.consume(transactions.into_iter());
+    assert_eq!(bursar.client_balance(1).unwrap().held, dec!(12.5));
+}
+
+#[test]
+fn processing_order() {
+    let mut bursar = Bursar::new().with_processing_order(true);
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 1, 5, Some(dec!(10))),
+            Transaction::new(Op::Withdrawal, 1, 3, Some(dec!(20))),
+            Transaction::new(Op::Dispute, 1, 5, None),
+            Transaction::new(Op::Resolve, 1, 9, None),
+            Transaction::new(Op::Deposit, 2, 1, Some(dec!(1))),
+        ]
+        .into_iter(),
+    );
+
+    assert_eq!(bursar.processed_order(), &[5, 3, 5, 9, 1]);
+    assert_eq!(bursar.stats().rejected, 2);
+}
+
+#[test]
+fn resolve_all_of_client() {
+    let mut bursar = Bursar::new();
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(Op::Deposit, 1, 2, Some(dec!(20))),
+            Transaction::new(Op::Deposit, 1, 3, Some(dec!(5))),
+            Transaction::new(Op::Deposit, 2, 4, Some(dec!(7))),
+            Transaction::new(Op::Dispute, 1, 1, None),
+            Transaction::new(Op::Dispute, 1, 2, None),
+            Transaction::new(Op::Dispute, 2, 4, None),
+        ]
+        .into_iter(),
+    );
+    assert_eq!(bursar.client_balance(1).unwrap().held, dec!(30));
+
+    assert_eq!(bursar.resolve_all(1), 2);
+
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!(client.held, dec!(0));
+    assert_eq!(client.available, dec!(35));
+    assert_eq!(bursar.client_balance(2).unwrap().held, dec!(7));
+}
+
+#[test]
+fn zero_epsilon() {
+    let mut bursar = Bursar::new()
+        .with_zero_epsilon(dec!(0.00001))
+        .with_zero_balances_skipped(true)
+        .with_client_map(ClientMapKind::BTree);
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(0.00000001))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 2, 2, Some(dec!(1.00000001))));
+
+    let mut output = Vec::new();
+    bursar.write_results(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n2,1.0000,0.0000,1.0000,false\n"
+    );
+
+    let mut bursar = Bursar::new().with_zero_epsilon(dec!(0.00001));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(0.00000001))));
+    let mut output = Vec::new();
+    bursar.write_results(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n1,0.0000,0.0000,0.0000,false\n"
+    );
+}
+
+#[test]
+fn invalid_utf8_record_is_skipped() {
+    capture_logs();
+    let source: &[u8] =
+        b"type,client,tx,amount\ndeposit,1,1,1.0\ndepo\xffsit,1,2,2.0\ndeposit,1,3,3.0\n";
+    let mut reader = transaction_reader(source, &HashMap::new()).unwrap();
+    let ids: Vec<u32> = read_transactions(&mut reader)
+        .map(|tx| tx.tx_id())
+        .collect();
+    assert_eq!(ids, vec![1, 3]);
+    assert!(captured_logs()
+        .iter()
+        .any(|line| line.contains("invalid utf-8 in field 1 at byte 38")));
+}
+
+#[test]
+fn memory_estimate_grows() {
+    let mut bursar = Bursar::new();
+    assert_eq!(bursar.approx_memory_bytes(), 0);
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(1.0))));
+    let one = bursar.approx_memory_bytes();
+    assert!(one > 0);
+    bursar.process_transaction(Transaction::new(Op::Deposit, 2, 2, Some(dec!(1.0))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 2, 2, None));
+    assert!(bursar.approx_memory_bytes() > one);
+}
+
+#[test]
+fn undisputed_chargeback_error() {
+    let txs = || {
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(1.0))),
+            Transaction::new(Op::Resolve, 1, 1, None),
+            Transaction::new(Op::Chargeback, 1, 1, None),
+        ]
+    };
+
+    let mut bursar = Bursar::new().with_reject_tracking(true);
+    bursar.consume(txs().into_iter());
+    let reasons: Vec<TxError> = bursar.rejects().iter().map(|(_, err)| *err).collect();
+    assert_eq!(reasons, vec![TxError::NotDisputed, TxError::NotDisputed]);
+    assert_eq!(bursar.stats().undisputed_chargebacks, 0);
+
+    let mut bursar = Bursar::new()
+        .with_reject_tracking(true)
+        .with_undisputed_chargeback_error(true);
+    bursar.consume(txs().into_iter());
+    let reasons: Vec<TxError> = bursar.rejects().iter().map(|(_, err)| *err).collect();
+    assert_eq!(
+        reasons,
+        vec![TxError::NotDisputed, TxError::UndisputedChargeback]
+    );
+    let stats = bursar.stats();
+    assert_eq!((stats.rejected, stats.undisputed_chargebacks), (2, 1));
+    assert!(!bursar.client_balance(1).unwrap().locked);
+}
+
+#[test]
+fn opening_balances() {
+    let mut bursar = Bursar::from_opening_balances(
+        vec![
+            (1, dec!(10.0), dec!(2.5), false),
+            (2, dec!(3.0), dec!(0), true),
+        ]
+        .into_iter(),
+    );
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(5.0))));
+
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!((client.available, client.held), (dec!(15.0), dec!(2.5)));
+    assert_eq!(client.total, dec!(17.5));
+    let client = bursar.client_balance(2).unwrap();
+    assert!(client.locked);
+    assert_eq!(client.total, dec!(3.0));
+    assert_eq!(bursar.avg_deposit(1), Some(dec!(8.75)));
+    assert_eq!(bursar.avg_deposit(2), Some(dec!(3.0)));
+}
+
+#[test]
+fn max_held_ratio() {
+    let mut bursar = Bursar::new()
+        .with_max_held_ratio(dec!(2))
+        .with_reject_tracking(true);
+    // both clients are at 10 available
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(30.0))));
+    bursar.process_transaction(Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(20.0))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 2, 3, Some(dec!(15.0))));
+    bursar.process_transaction(Transaction::new(Op::Withdrawal, 2, 4, Some(dec!(5.0))));
+
+    bursar.process_transaction(Transaction::new(Op::Dispute, 1, 1, None));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 2, 3, None));
+
+    assert_eq!(bursar.rejects().len(), 1);
+    assert_eq!(bursar.rejects()[0].0.tx_id(), 1);
+    assert_eq!(bursar.rejects()[0].1, TxError::ExceedsHeldRatio);
+    assert_eq!(bursar.client_balance(1).unwrap().held, dec!(0));
+    assert_eq!(bursar.client_balance(2).unwrap().held, dec!(15.0));
+}
+
+#[test]
+fn sealed_ledger() {
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(1.0))));
+    bursar.seal();
+    assert!(bursar.is_sealed());
+
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Deposit, 1, 2, Some(dec!(5.0)))),
+        Err(TxError::Sealed)
+    );
+    assert_eq!(
+        bursar.dispute_batch(1, &[1]),
+        Err(BatchDisputeError::Sealed)
+    );
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!((client.available, client.held), (dec!(1.0), dec!(0)));
+    assert_eq!(bursar.stats().processed, 1);
+
+    // the other mutating methods leave the output untouched as well
+    bursar.tombstone_client(1);
+    bursar.register_client(9);
+    assert_eq!(bursar.split_off(|_| true).stats().clients, 0);
+    assert!(!bursar.unlock(1));
+    let mut output = Vec::new();
+    bursar.write_results(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n1,1.0,0.0000,1.0,false\n"
+    );
+}
+
+#[test]
+fn disputes_across_sources() {
+    let first: &[u8] = b"type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,5.0\n";
+    let second: &[u8] = b"type,client,tx,amount\ndispute,1,1,\n";
+    let mut bursar = Bursar::new();
+    bursar.consume_sources(vec![first, second]).unwrap();
+
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!((client.available, client.held), (dec!(5.0), dec!(10.0)));
+}
+
+#[test]
+fn locked_deposit_policies() {
+    let lock = |bursar: &mut Bursar| {
+        bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10.0))));
+        bursar.process_transaction(Transaction::new(Op::Dispute, 1, 1, None));
+        bursar.process_transaction(Transaction::new(Op::Chargeback, 1, 1, None));
+        bursar.process_transaction(Transaction::new(Op::Deposit, 1, 2, Some(dec!(5.0))));
+    };
+
+    let mut bursar = Bursar::new().with_locked_deposit_policy(LockedDepositPolicy::Queue);
+    lock(&mut bursar);
+    assert_eq!(bursar.client_balance(1).unwrap().available, dec!(0));
+    assert!(bursar.unlock(1));
+    let client = bursar.client_balance(1).unwrap();
+    assert!(!client.locked);
+    assert_eq!(client.available, dec!(5.0));
+    assert!(!bursar.unlock(1));
+    let summary = bursar.summary();
+    assert_eq!(summary.deposits.applied, 2);
+    assert_eq!(summary.deposits.rejected, 0);
+
+    let mut bursar = Bursar::new()
+        .with_locked_deposit_policy(LockedDepositPolicy::Reject)
+        .with_reject_tracking(true);
+    lock(&mut bursar);
+    assert_eq!(bursar.rejects()[0].1, TxError::AccountLocked);
+    assert!(bursar.unlock(1));
+    assert_eq!(bursar.client_balance(1).unwrap().available, dec!(0));
+}
+
+#[test]
+fn state_commitment() {
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(1.0))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 2, 2, Some(dec!(2.0))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 3, 3, Some(dec!(3.0))));
+    let root = bursar.state_commitment().unwrap();
+    assert_eq!(root, bursar.state_commitment().unwrap());
+
+    let mut leaves = bursar.commitment_leaves().unwrap();
+    assert_eq!(leaves[1], b"2,2.0,0.0000,2.0,false\n".to_vec());
+    assert!(verify_commitment(&leaves, &root));
+    leaves[1] = b"2,2.1,0.0000,2.1,false\n".to_vec();
+    assert!(!verify_commitment(&leaves, &root));
+
+    bursar.process_transaction(Transaction::new(Op::Dispute, 3, 3, None));
+    assert_ne!(bursar.state_commitment().unwrap(), root);
+}
+
+#[test]
+fn replay_audit_log() {
+    let mut bursar = Bursar::new().with_audit_log(true);
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(10.0))),
+            Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(2.5))),
+            Transaction::new(Op::Deposit, 2, 3, Some(dec!(4.0))),
+            Transaction::new(Op::Dispute, 1, 1, Some(dec!(5.0))),
+            Transaction::new(Op::Resolve, 1, 1, None),
+            Transaction::new(Op::Dispute, 2, 3, None),
+            Transaction::new(Op::Chargeback, 2, 3, None),
+        ]
+        .into_iter(),
+    );
+    assert_eq!(bursar.audit_log().len(), 7);
+
+    let replayed = Bursar::from_audit_log(bursar.audit_log());
+    for client_id in [1, 2] {
+        assert_eq!(
+            replayed.client_balance(client_id),
+            bursar.client_balance(client_id)
+        );
+    }
+}
+
+#[test]
+fn replay_audit_log_of_adjusted_amounts() {
+    let replays = |bursar: Bursar, transactions: Vec<Transaction>| {
+        let mut bursar = bursar.with_audit_log(true);
+        bursar.consume(transactions.into_iter());
+        let replayed = Bursar::from_audit_log(bursar.audit_log());
+        assert_eq!(replayed.exact_balances(1), bursar.exact_balances(1));
+        bursar
+    };
+
+    // the swept amount of a partial withdrawal
+    let bursar = replays(
+        Bursar::new().with_partial_withdrawals(true),
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(15))),
+        ],
+    );
+    assert_eq!(bursar.audit_log()[1].amount, dec!(10));
+
+    // the covered part of a capped dispute
+    let bursar = replays(
+        Bursar::new().with_uncovered_disputes_capped(true),
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(6))),
+            Transaction::new(Op::Dispute, 1, 1, None),
+        ],
+    );
+    assert_eq!(bursar.audit_log()[2].amount, dec!(4));
+
+    // the scaled amount of a percent dispute
+    let bursar = replays(
+        Bursar::new(),
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(Op::Dispute, 1, 1, None).with_percent(dec!(30)),
+        ],
+    );
+    assert_eq!(bursar.audit_log()[1].amount, dec!(3));
+}
+
+#[test]
+fn partial_withdrawals() {
+    capture_logs();
+    let mut bursar = Bursar::new().with_partial_withdrawals(true);
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(15))));
+    assert_eq!(bursar.client_balance(1).unwrap().available, dec!(0));
+    assert!(captured_logs()
+        .iter()
+        .any(|line| line.contains("withdrawal '2' short by 5")));
+    // the record keeps the swept amount
+    assert_eq!(bursar.transactions.get(2).unwrap().amount, dec!(10));
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Withdrawal, 1, 3, Some(dec!(1)))),
+        Err(TxError::InsufficientFunds)
+    );
+}
+
+#[test]
+fn exact_balances() {
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(1.123456789))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 2, Some(dec!(0.000000001))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 1, 2, None));
+    assert_eq!(
+        bursar.exact_balances(1),
+        Some((dec!(1.123456789), dec!(0.000000001)))
+    );
+    assert_eq!(bursar.exact_balances(2), None);
+
+    let mut output = Vec::new();
+    bursar.write_results(&mut output).unwrap();
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .contains("1,1.1235,0.0000,1.1235,false"));
+}
+
+#[test]
+fn dispute_recency() {
+    let mut bursar = Bursar::new().with_dispute_recency(3);
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))));
+    for tx_id in 2..6 {
+        bursar.process_transaction(Transaction::new(Op::Deposit, 2, tx_id, Some(dec!(1))));
+    }
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Dispute, 1, 1, None)),
+        Err(TxError::DisputeTooLate)
+    );
+
+    let mut bursar = Bursar::new().with_dispute_recency(3);
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 2, 2, Some(dec!(1))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 2, 3, Some(dec!(1))));
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Dispute, 1, 1, None)),
+        Ok(())
+    );
+}
+
+#[test]
+fn run_batch() {
+    let snapshots = run(vec![
+        Transaction::new(Op::Deposit, 2, 1, Some(dec!(3.0))),
+        Transaction::new(Op::Deposit, 1, 2, Some(dec!(5.0))),
+        Transaction::new(Op::Withdrawal, 1, 3, Some(dec!(1.5))),
+        Transaction::new(Op::Dispute, 2, 1, None),
+    ]);
+    let balances: Vec<(u16, Decimal, Decimal, Decimal)> = snapshots
+        .iter()
+        .map(|client| {
+            (
+                client.client_id,
+                client.available,
+                client.held,
+                client.total,
+            )
+        })
+        .collect();
+    assert_eq!(
+        balances,
+        vec![
+            (1, dec!(3.5), dec!(0), dec!(3.5)),
+            (2, dec!(0), dec!(3.0), dec!(3.0))
+        ]
+    );
+}
+
+#[test]
+fn dispute_analysis() {
+    let source: &[u8] = b"type,client,tx,amount
+deposit,1,1,10.0
+chargeback,1,1,
+dispute,1,1,
+dispute,1,1,
+chargeback,1,1,
+resolve,1,1,
+dispute,1,7,
+";
+    let analysis = analyze_disputes(source).unwrap();
+    let problems: Vec<(u64, u32, DisputeIssue)> = analysis
+        .problems
+        .iter()
+        .map(|problem| (problem.line, problem.tx_id, problem.issue))
+        .collect();
+    assert_eq!(
+        problems,
+        vec![
+            (3, 1, DisputeIssue::ChargebackWithoutDispute),
+            (5, 1, DisputeIssue::DoubleDispute),
+            (7, 1, DisputeIssue::AfterChargeback),
+            (8, 7, DisputeIssue::UnknownTransaction),
+        ]
+    );
+}
+
+#[test]
+fn buffered_output() {
+    let txs = || {
+        (1..=50).map(|tx_id| Transaction::new(Op::Deposit, tx_id as u16, tx_id, Some(dec!(1.5))))
+    };
+    let mut bursar = Bursar::new().with_client_map(ClientMapKind::BTree);
+    bursar.consume(txs());
+    let mut expected = Vec::new();
+    bursar.write_results(&mut expected).unwrap();
+
+    for capacity in [1, 64, 1 << 16] {
+        let mut bursar = Bursar::new()
+            .with_client_map(ClientMapKind::BTree)
+            .with_output_buffer(capacity);
+        bursar.consume(txs());
+        let mut output = Vec::new();
+        bursar.write_results(&mut output).unwrap();
+        assert_eq!(output, expected);
+    }
+}
+
+#[test]
+fn checksum_column() {
+    let mut bursar = Bursar::new()
+        .with_checksum_column(true)
+        .with_client_map(ClientMapKind::BTree);
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(1.5))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 2, 2, Some(dec!(2.25))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 2, 2, None));
+
+    let mut output = Vec::new();
+    bursar.write_results(&mut output).unwrap();
+    let mut reader = csv::Reader::from_reader(output.as_slice());
+    assert_eq!(
+        reader.headers().unwrap(),
+        vec!["client", "available", "held", "total", "locked", "checksum"]
+    );
+    let mut rows = 0;
+    for record in reader.records() {
+        let record = record.unwrap();
+        let fields: Vec<&str> = record.iter().take(4).collect();
+        assert_eq!(&record[5], row_checksum(&fields));
+        rows += 1;
+    }
+    assert_eq!(rows, 2);
+    assert_ne!(
+        row_checksum(&["1", "1.5", "0.0000", "1.5"]),
+        row_checksum(&["1", "1.6", "0.0000", "1.6"])
+    );
+}
+
+#[test]
+fn split_off_locked_clients() {
+    let mut bursar = Bursar::new();
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(10.0))),
+            Transaction::new(Op::Deposit, 1, 2, Some(dec!(4.0))),
+            Transaction::new(Op::Dispute, 1, 1, None),
+            Transaction::new(Op::Chargeback, 1, 1, None),
+            Transaction::new(Op::Dispute, 1, 2, None),
+            Transaction::new(Op::Deposit, 2, 3, Some(dec!(3.0))),
+            Transaction::new(Op::Dispute, 2, 3, None),
+        ]
+        .into_iter(),
+    );
+
+    let mut locked = bursar.split_off(|client| client.locked);
+    assert!(bursar.client_balance(1).is_none());
+    assert!(locked.client_balance(2).is_none());
+    let retained = |bursar: &Bursar| {
+        let mut ids: Vec<u32> = bursar.retained_transactions().map(|(id, _)| id).collect();
+        ids.sort_unstable();
+        ids
+    };
+    assert_eq!(retained(&bursar), vec![3]);
+    assert_eq!(retained(&locked), vec![1, 2]);
+
+    // the open disputes moved along with their clients
+    locked.process_transaction(Transaction::new(Op::Resolve, 1, 2, None));
+    bursar.process_transaction(Transaction::new(Op::Resolve, 2, 3, None));
+    assert_eq!(locked.client_balance(1).unwrap().available, dec!(4.0));
+    assert_eq!(bursar.client_balance(2).unwrap().available, dec!(3.0));
+}
+
+#[test]
+fn withdrawal_of_exact_balance() {
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10.25))));
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(10.25)))),
+        Ok(())
+    );
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!((client.available, client.total), (dec!(0), dec!(0)));
+}
+
+#[test]
+fn overdrawing_withdrawal() {
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10.25))));
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(10.26)))),
+        Err(TxError::InsufficientFunds)
+    );
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!((client.available, client.held), (dec!(10.25), dec!(0)));
+    assert_eq!(bursar.net_flow(1), Some(dec!(10.25)));
+    assert_eq!(bursar.stats().rejected, 1);
+}
+
+#[test]
+fn locked_account_is_frozen() {
+    let mut bursar = Bursar::new().with_reject_tracking(true);
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(10.0))),
+            Transaction::new(Op::Deposit, 1, 2, Some(dec!(5.0))),
+            Transaction::new(Op::Dispute, 1, 1, None),
+            Transaction::new(Op::Chargeback, 1, 1, None),
+            Transaction::new(Op::Deposit, 1, 3, Some(dec!(7.0))),
+            Transaction::new(Op::Withdrawal, 1, 4, Some(dec!(1.0))),
+            Transaction::new(Op::Dispute, 1, 2, None),
+        ]
+        .into_iter(),
+    );
+    let client = bursar.client_balance(1).unwrap();
+    assert!(client.locked);
+    assert_eq!((client.available, client.held), (dec!(5.0), dec!(0)));
+    let reasons: Vec<TxError> = bursar.rejects().iter().map(|(_, err)| *err).collect();
+    assert_eq!(reasons, vec![TxError::AccountLocked; 3]);
+}
+
+#[test]
+fn config_from_file() {
+    let path = std::env::temp_dir().join(format!("bursar-config-{}.json", std::process::id()));
+    fs::write(
+        &path,
+        r#"{"rounding": "half_away_from_zero", "locked_repr": "yes_no"}"#,
+    )
+    .unwrap();
+    let config = BursarConfig::from_file(&path).unwrap();
+    assert_eq!(config.rounding, Rounding::HalfAwayFromZero);
+
+    let mut bursar = Bursar::with_config(config);
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(1.00005))));
+    let mut output = Vec::new();
+    bursar.write_results(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n1,1.0001,0.0000,1.0001,N\n"
+    );
+
+    fs::write(&path, r#"{"roundin": "bankers"}"#).unwrap();
+    let err = BursarConfig::from_file(&path).unwrap_err();
+    assert!(err.to_string().contains("unknown field `roundin`"));
+
+    fs::write(&path, r#"{"precision": 29}"#).unwrap();
+    let err = BursarConfig::from_file(&path).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn client_timeline_json() {
+    let mut bursar = Bursar::new().with_audit_log(true);
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(10.0))),
+            Transaction::new(Op::Deposit, 2, 2, Some(dec!(3.0))),
+            Transaction::new(Op::Withdrawal, 1, 3, Some(dec!(4.0))),
+            Transaction::new(Op::Dispute, 1, 1, Some(dec!(5.0))),
+            Transaction::new(Op::Resolve, 1, 1, None),
+        ]
+        .into_iter(),
+    );
+
+    let mut output = Vec::new();
+    bursar.write_client_timeline_json(1, &mut output).unwrap();
+    let timeline: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let events: Vec<(&str, &str, &str, &str)> = timeline
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| {
+            (
+                event["type"].as_str().unwrap(),
+                event["amount"].as_str().unwrap(),
+                event["available"].as_str().unwrap(),
+                event["held"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        events,
+        vec![
+            ("deposit", "10.0", "10.0", "0"),
+            ("withdrawal", "4.0", "6.0", "0"),
+            ("dispute", "5.0", "1.0", "5.0"),
+            ("resolve", "5.0", "6.0", "0.0"),
+        ]
+    );
+}
+
+#[test]
+fn second_resolve_is_rejected() {
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 2, Some(dec!(5))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 1, 1, None));
+    bursar.process_transaction(Transaction::new(Op::Resolve, 1, 1, None));
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Resolve, 1, 1, None)),
+        Err(TxError::NotDisputed)
+    );
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!((client.available, client.held), (dec!(15), dec!(0)));
+}
+
+#[test]
+fn chargeback_after_resolve_is_rejected() {
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 1, 1, None));
+    bursar.process_transaction(Transaction::new(Op::Resolve, 1, 1, None));
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Chargeback, 1, 1, None)),
+        Err(TxError::NotDisputed)
+    );
+    let client = bursar.client_balance(1).unwrap();
+    assert!(!client.locked);
+    assert_eq!((client.available, client.held), (dec!(10), dec!(0)));
+}
+
+#[test]
+fn double_dispute_is_rejected() {
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 2, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 1, 1, None));
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Dispute, 1, 1, None)),
+        Err(TxError::AlreadyDisputed)
+    );
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!((client.available, client.held), (dec!(10), dec!(10)));
+}
+
+#[test]
+fn dispute_after_resolve() {
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 1, 1, None));
+    bursar.process_transaction(Transaction::new(Op::Resolve, 1, 1, None));
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Dispute, 1, 1, None)),
+        Ok(())
+    );
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!((client.available, client.held), (dec!(0), dec!(10)));
+}
+
+#[test]
+fn error_budget_exceeded() {
+    // every withdrawal overdraws
+    let txs = (1..=40).map(|tx_id| match tx_id % 2 {
+        0 => Transaction::new(Op::Deposit, 1, tx_id, Some(dec!(1))),
+        _ => Transaction::new(Op::Withdrawal, 1, tx_id, Some(dec!(5))),
+    });
+    let mut bursar = Bursar::new();
+    assert_eq!(
+        bursar.consume_with_error_budget(txs, 0.25),
+        Err(ErrorBudgetExceeded {
+            processed: 10,
+            rejected: 5
+        })
+    );
+    assert_eq!(bursar.stats().processed, 10);
+    // the deposits before giving up stay applied
+    assert_eq!(bursar.client_balance(1).unwrap().available, dec!(5));
+
+    // a resolve still waiting for its dispute is processed on giving up
+    let txs = std::iter::once(Transaction::new(Op::Resolve, 1, 1, None))
+        .chain((2..=40).map(|tx_id| Transaction::new(Op::Withdrawal, 1, tx_id, Some(dec!(5)))));
+    let mut bursar = Bursar::new().with_reorder_window(100);
+    assert_eq!(
+        bursar.consume_with_error_budget(txs, 0.25),
+        Err(ErrorBudgetExceeded {
+            processed: 10,
+            rejected: 9
+        })
+    );
+    assert!(bursar.reorder_buffer.is_empty());
+    assert_eq!(bursar.stats().rejected, 10);
+
+    let txs = (1..=40).map(|tx_id| Transaction::new(Op::Deposit, 1, tx_id, Some(dec!(1))));
+    let mut bursar = Bursar::new();
+    assert_eq!(bursar.consume_with_error_budget(txs, 0.25), Ok(()));
+}
+
+#[test]
+fn clock_ticks_for_every_transaction() {
+    let mut bursar = Bursar::new()
+        .with_client_id_map(HashMap::from([(1, 1)]))
+        .with_unmapped_clients_rejected(true);
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(1))));
+    assert_eq!(bursar.clock, 1);
+    // rejected before being applied
+    bursar.process_transaction(Transaction::new(Op::Deposit, 2, 2, Some(dec!(1))));
+    assert_eq!(bursar.clock, 2);
+    // rejected while being applied
+    bursar.process_transaction(Transaction::new(Op::Withdrawal, 1, 3, Some(dec!(5))));
+    assert_eq!(bursar.clock, 3);
+}
+
+#[test]
+fn client_id_map() {
+    let txs = || {
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(2.0))),
+            Transaction::new(Op::Deposit, 2, 2, Some(dec!(3.0))),
+        ]
+        .into_iter()
+    };
+    let mut bursar = Bursar::new()
+        .with_client_id_map(HashMap::from([(1, 1000)]))
+        .with_client_map(ClientMapKind::BTree);
+    bursar.consume(txs());
+    let mut output = Vec::new();
+    bursar.write_results(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n2,3.0,0.0000,3.0,false\n1000,2.0,0.0000,2.0,false\n"
+    );
+
+    let mut bursar = Bursar::new()
+        .with_client_id_map(HashMap::from([(1, 1000)]))
+        .with_unmapped_clients_rejected(true)
+        .with_reject_tracking(true);
+    bursar.consume(txs());
+    assert!(bursar.client_balance(2).is_none());
+    assert_eq!(bursar.rejects()[0].1, TxError::UnmappedClient);
+}
+
+#[test]
+fn dispute_of_foreign_transaction() {
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 2, 2, Some(dec!(5))));
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Dispute, 2, 1, None)),
+        Err(TxError::ForeignTransaction)
+    );
+    bursar.process_transaction(Transaction::new(Op::Dispute, 1, 1, None));
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Chargeback, 2, 1, None)),
+        Err(TxError::ForeignTransaction)
+    );
+
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!((client.available, client.held), (dec!(0), dec!(10)));
+    assert!(!client.locked);
+    let client = bursar.client_balance(2).unwrap();
+    assert_eq!((client.available, client.held), (dec!(5), dec!(0)));
+    assert!(!client.locked);
+}
+
+// hands out a single byte per read
+#[cfg(test)]
+struct Trickle<'a>(&'a [u8]);
+
+#[cfg(test)]
+impl io::Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match (self.0.split_first(), buf.first_mut()) {
+            (Some((byte, rest)), Some(target)) => {
+                *target = *byte;
+                self.0 = rest;
+                Ok(1)
+            }
+            _ => Ok(0),
+        }
+    }
+}
+
+// accepts `limit` bytes, failing every write after that
+#[cfg(test)]
+struct FailingWriter {
+    limit: usize,
+}
+
+#[cfg(test)]
+impl io::Write for FailingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.limit == 0 {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"));
+        }
+        let written = buf.len().min(self.limit);
+        self.limit -= written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn write_results_reports_io_errors() {
+    let mut bursar = Bursar::new();
+    for client_id in 1..=100 {
+        bursar.process_transaction(Transaction::new(
+            Op::Deposit,
+            client_id,
+            u32::from(client_id),
+            Some(dec!(1.5)),
+        ));
+    }
+    let err = bursar
+        .write_results(FailingWriter { limit: 64 })
+        .unwrap_err();
+    match err.kind() {
+        csv::ErrorKind::Io(err) => assert_eq!(err.kind(), io::ErrorKind::BrokenPipe),
+        kind => panic!("unexpected error {:?}", kind),
+    }
+    assert!(bursar
+        .write_results(FailingWriter { limit: 1 << 16 })
+        .is_ok());
+}
 
-    let client = bursar.clients.get(&client_id);
-    assert!(client.is_some());
-    let client = client.unwrap();
-    assert_eq!(client.total(), dec!(52));
-    assert_eq!(client.available, dec!(42));
-    assert_eq!(client.held, dec!(10));
-    assert_eq!(client.locked, false);
+#[test]
+fn chunked_results() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    type Outputs = Rc<RefCell<Vec<Vec<u8>>>>;
+
+    struct ChunkBuffer(usize, Outputs);
+
+    impl io::Write for ChunkBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.1.borrow_mut()[self.0].write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut bursar = Bursar::new();
+    for client_id in [5, 3, 1, 4, 2] {
+        bursar.process_transaction(Transaction::new(
+            Op::Deposit,
+            client_id,
+            u32::from(client_id),
+            Some(dec!(1)),
+        ));
+    }
+
+    let outputs = Outputs::default();
+    let chunk_outputs = Rc::clone(&outputs);
+    bursar
+        .write_results_chunked(2, move |chunk| {
+            chunk_outputs.borrow_mut().push(Vec::new());
+            Box::new(ChunkBuffer(chunk, Rc::clone(&chunk_outputs)))
+        })
+        .unwrap();
+
+    let chunks: Vec<String> = outputs
+        .borrow()
+        .iter()
+        .map(|chunk| String::from_utf8(chunk.clone()).unwrap())
+        .collect();
+    let rows: Vec<usize> = chunks
+        .iter()
+        .map(|chunk| chunk.lines().count() - 1)
+        .collect();
+    assert_eq!(rows, vec![2, 2, 1]);
+    assert_eq!(
+        chunks[1],
+        "client,available,held,total,locked\n3,1,0.0000,1,false\n4,1,0.0000,1,false\n"
+    );
 }
 
 #[test]
-fn resolve_dispute() {
+fn held_matches_disputed() {
     let mut bursar = Bursar::new();
-    let client_id = 1;
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(10.0))),
+            Transaction::new(Op::Deposit, 1, 2, Some(dec!(4.0))),
+            Transaction::new(Op::Deposit, 2, 3, Some(dec!(3.0))),
+            Transaction::new(Op::Dispute, 1, 1, None),
+            Transaction::new(Op::Dispute, 1, 2, None),
+            Transaction::new(Op::Dispute, 2, 3, Some(dec!(1.5))),
+            Transaction::new(Op::Resolve, 1, 2, None),
+        ]
+        .into_iter(),
+    );
+    assert_eq!(bursar.held_matches_disputed(), Ok(()));
+    assert_eq!(bursar.verify_invariants(), Ok(()));
 
-    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 1, Some(dec!(10))));
-    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 2, Some(dec!(42))));
-    bursar.process_transaction(Transaction::new(Op::Dispute, client_id, 1, None));
-    bursar.process_transaction(Transaction::new(Op::Resolve, client_id, 1, None));
+    bursar.clients.get_mut(&2).unwrap().held += dec!(0.25);
+    assert_eq!(bursar.held_matches_disputed(), Err(dec!(0.25)));
+    assert_eq!(
+        bursar.verify_invariants(),
+        Err(vec![InvariantViolation::HeldMismatchesDisputed {
+            held: dec!(11.75),
+            disputed: dec!(11.5),
+        }])
+    );
+}
 
-    let client = bursar.clients.get(&client_id);
-    assert!(client.is_some());
-    let client = client.unwrap();
-    assert_eq!(client.total(), dec!(52));
-    assert_eq!(client.available, dec!(52));
-    assert_eq!(client.held, dec!(0));
-    assert_eq!(client.locked, false);
+#[test]
+fn collected_errors() {
+    let mut bursar = Bursar::new();
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(10.0))),
+            Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(11.0))),
+            Transaction::new(Op::Dispute, 1, 9, None),
+            Transaction::new(Op::Resolve, 1, 1, None),
+            Transaction::new(Op::Deposit, 1, 3, None),
+            Transaction::new(Op::Dispute, 1, 1, None),
+            Transaction::new(Op::Dispute, 1, 1, None),
+            Transaction::new(Op::Chargeback, 1, 1, None),
+            Transaction::new(Op::Deposit, 1, 4, Some(dec!(1.0))),
+        ]
+        .into_iter(),
+    );
+    assert_eq!(
+        bursar.errors(),
+        &[
+            TxError::InsufficientFunds,
+            TxError::UnknownTransaction,
+            TxError::NotDisputed,
+            TxError::MissingAmount,
+            TxError::AlreadyDisputed,
+            TxError::AccountLocked,
+        ]
+    );
+    assert_eq!(bursar.stats().rejected, 6);
 }
 
 #[test]
-fn chargeback_dispute() {
+fn rejected_withdrawal_can_be_retried() {
     let mut bursar = Bursar::new();
-    let client_id = 1;
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(3))));
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(5)))),
+        Err(TxError::InsufficientFunds)
+    );
+    assert!(!bursar.transactions.contains(2));
 
-    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 1, Some(dec!(10))));
-    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 2, Some(dec!(42))));
-    bursar.process_transaction(Transaction::new(Op::Dispute, client_id, 1, None));
-    bursar.process_transaction(Transaction::new(Op::Chargeback, client_id, 1, None));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 3, Some(dec!(2))));
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(5)))),
+        Ok(())
+    );
+    assert_eq!(bursar.client_balance(1).unwrap().available, dec!(0));
+}
 
-    let client = bursar.clients.get(&client_id);
-    assert!(client.is_some());
-    let client = client.unwrap();
-    assert_eq!(client.total(), dec!(42));
-    assert_eq!(client.available, dec!(42));
-    assert_eq!(client.held, dec!(0));
-    assert_eq!(client.locked, true);
+#[test]
+fn duplicate_tx_id() {
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))));
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Deposit, 1, 1, Some(dec!(25)))),
+        Err(TxError::DuplicateTxId)
+    );
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Withdrawal, 1, 1, Some(dec!(5)))),
+        Err(TxError::DuplicateTxId)
+    );
+    assert_eq!(bursar.client_balance(1).unwrap().available, dec!(10));
+
+    // the dispute uses the amount of the first deposit
+    bursar.process_transaction(Transaction::new(Op::Dispute, 1, 1, None));
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!((client.available, client.held), (dec!(0), dec!(10)));
 }
 
 #[test]
-fn false_resolve() {
+fn zero_padded_client_ids() {
+    let mut bursar = Bursar::new().with_client_id_width(6);
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(1))));
+    let mut output = Vec::new();
+    bursar.write_results(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n000001,1,0.0000,1,false\n"
+    );
+}
+
+#[test]
+fn transaction_budget() {
+    let budget = Duration::from_secs(1);
+    let mut bursar = Bursar::new().with_transaction_budget(budget);
+    let started = Instant::now();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(1.5))));
+    bursar.process_transaction(Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(0.5))));
+    assert!(started.elapsed() < budget);
+    assert_eq!(bursar.stats().slow_transactions, 0);
+    assert_eq!(bursar.client_balance(1).unwrap().available, dec!(1.0));
+}
+
+#[test]
+fn deposit_without_amount() {
     let mut bursar = Bursar::new();
-    let client_id = 1;
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Deposit, 1, 1, None)),
+        Err(TxError::MissingAmount)
+    );
+    assert_eq!(bursar.retained_transactions().count(), 0);
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Dispute, 1, 1, None)),
+        Err(TxError::UnknownTransaction)
+    );
+}
 
-    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 1, Some(dec!(10))));
-    bursar.process_transaction(Transaction::new(Op::Deposit, client_id, 2, Some(dec!(42))));
-    bursar.process_transaction(Transaction::new(Op::Resolve, client_id, 1, None));
+#[cfg(feature = "arrow")]
+#[test]
+fn arrow_batch() {
+    use arrow::array::{Array, Decimal128Array};
 
-    let client = bursar.clients.get(&client_id);
-    assert!(client.is_some());
-    let client = client.unwrap();
-    assert_eq!(client.total(), dec!(52));
-    assert_eq!(client.available, dec!(52));
-    assert_eq!(client.held, dec!(0));
-    assert_eq!(client.locked, false);
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 2, 1, Some(dec!(1.5))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 2, Some(dec!(0.12345))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 2, 1, None));
+
+    let batch = bursar.to_arrow_batch().unwrap();
+    assert_eq!(batch.num_rows(), 2);
+    let held = batch
+        .column_by_name("held")
+        .unwrap()
+        .as_any()
+        .downcast_ref::<Decimal128Array>()
+        .unwrap();
+    assert_eq!(held.value_as_string(0), "0.0000");
+    assert_eq!(held.value_as_string(1), "1.5000");
+    assert_eq!(held.len(), 2);
+
+    let config = BursarConfig {
+        precision: Some(30),
+        ..Default::default()
+    };
+    assert!(Bursar::with_config(config).to_arrow_batch().is_err());
+}
+
+#[test]
+fn consume_reader() {
+    let source: &[u8] =
+        b"type,client,tx,amount\ndeposit,1,1,2.0\nbogus,1,2,1.0\nwithdrawal,1,3,0.5\n";
+    let mut bursar = Bursar::new();
+    bursar.consume_reader(source).unwrap();
+    assert_eq!(bursar.client_balance(1).unwrap().available, dec!(1.5));
+}
+
+#[test]
+fn capped_dispute_of_withdrawn_deposit() {
+    let mut bursar = Bursar::new().with_uncovered_disputes_capped(true);
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(100))));
+    bursar.process_transaction(Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(100))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 1, 1, None));
+
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!((client.available, client.held), (dec!(0), dec!(0)));
+    assert_eq!(bursar.uncovered(1), Some(dec!(100)));
+
+    bursar.process_transaction(Transaction::new(Op::Resolve, 1, 1, None));
+    assert_eq!(bursar.uncovered(1), Some(dec!(0)));
+}
+
+#[test]
+fn query_clients() {
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 2, 1, Some(dec!(4.0))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 2, Some(dec!(1.5))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 2, 1, None));
+
+    let client = bursar.client_balance(1).unwrap();
+    assert_eq!(
+        (client.available, client.held, client.total, client.locked),
+        (dec!(1.5), dec!(0), dec!(1.5), false)
+    );
+    let clients: Vec<(u16, Decimal, Decimal)> = bursar
+        .clients()
+        .map(|client| (client.client_id, client.held, client.total))
+        .collect();
+    assert_eq!(
+        clients,
+        vec![(1, dec!(0), dec!(1.5)), (2, dec!(4.0), dec!(4.0))]
+    );
+}
+
+#[test]
+fn streaming_output() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // hands out one line per read, noting the output seen before the last one
+    struct Lines {
+        lines: VecDeque<&'static str>,
+        output: Rc<RefCell<Vec<u8>>>,
+        before_last: Rc<RefCell<String>>,
+    }
+
+    impl io::Read for Lines {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let line = match self.lines.pop_front() {
+                Some(line) => line,
+                None => return Ok(0),
+            };
+            if self.lines.is_empty() {
+                *self.before_last.borrow_mut() =
+                    String::from_utf8(self.output.borrow().clone()).unwrap();
+            }
+            buf[..line.len()].copy_from_slice(line.as_bytes());
+            Ok(line.len())
+        }
+    }
+
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let before_last = Rc::new(RefCell::new(String::new()));
+    let source = Lines {
+        lines: VecDeque::from(vec![
+            "type,client,tx,amount\n",
+            "deposit,1,1,1.0\n",
+            "deposit,2,2,2.0\n",
+            "deposit,2,3,2.0\n",
+            "deposit,2,4,2.0\n",
+            "deposit,2,5,2.0\n",
+        ]),
+        output: Rc::clone(&output),
+        before_last: Rc::clone(&before_last),
+    };
+
+    let mut bursar = Bursar::new().with_dispute_window(2);
+    bursar
+        .consume_streaming_output(source, Output(Rc::clone(&output)))
+        .unwrap();
+
+    assert_eq!(
+        *before_last.borrow(),
+        "client,available,held,total,locked\n1,1,0.0000,1,false\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.borrow().clone()).unwrap(),
+        "client,available,held,total,locked\n1,1,0.0000,1,false\n2,8,0.0000,8,false\n"
+    );
+}
+
+#[test]
+fn last_processed_seq() {
+    let mut bursar = Bursar::new();
+    assert_eq!(bursar.last_processed_seq(), None);
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(5))).with_seq(100),
+            Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(2))).with_seq(101),
+            Transaction::new(Op::Withdrawal, 1, 3, Some(dec!(9))).with_seq(102),
+        ]
+        .into_iter(),
+    );
+    // the last withdrawal overdraws and isn't applied
+    assert_eq!(bursar.last_processed_seq(), Some(101));
+
+    let data = "type,client,tx,amount,seq\ndeposit,1,1,1.0,7\n";
+    let mut reader = csv::Reader::from_reader(data.as_bytes());
+    let tx: Transaction = reader.deserialize().next().unwrap().unwrap();
+    assert_eq!(tx.seq(), Some(7));
+}
+
+#[test]
+fn output_precision() {
+    let mut bursar = Bursar::new().with_precision(2);
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(1.23456))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 2, Some(dec!(0.11111))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 1, 2, None));
+    let mut output = Vec::new();
+    bursar.write_results(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n1,1.23,0.11,1.35,false\n"
+    );
+}
+
+#[test]
+#[should_panic(expected = "precision must be at most 28")]
+fn output_precision_above_decimal_scale() {
+    let _ = Bursar::new().with_precision(29);
+}
+
+#[test]
+fn semantically_equal_csvs() {
+    let a = "client,available,held,total,locked\n1,1.5,0,1.5,false\n2,0.1234,2.0,2.1234,true\n";
+    let b =
+        "client,available,held,total,locked\n2,0.1235,2.0000,2.1235,1\n1,1.5000,0.0000,1.5000,0\n";
+    assert!(csv_semantically_equal(a.as_bytes(), b.as_bytes()));
+
+    let c = "client,available,held,total,locked\n1,1.5,0,1.5,false\n2,0.2234,2.0,2.2234,true\n";
+    assert!(!csv_semantically_equal(a.as_bytes(), c.as_bytes()));
+    let d = "client,available,held,total,locked\n1,1.5,0,1.5,false\n";
+    assert!(!csv_semantically_equal(a.as_bytes(), d.as_bytes()));
+
+    // tombstones compare by their balances, zero padded ids by value
+    let e = "client,available,held,total,locked\n\
+             1,1.5,0,1.5,false\n\
+             anonymized,3,0,3,false\n\
+             anonymized,7.5,0,7.5,false\n";
+    let f = "client,available,held,total,locked\n\
+             anonymized,7.5000,0.0000,7.5000,false\n\
+             001,1.5,0,1.5,false\n\
+             anonymized,3.0000,0.0000,3.0000,false\n";
+    assert!(csv_semantically_equal(e.as_bytes(), f.as_bytes()));
+    let g = "client,available,held,total,locked\n\
+             1,1.5,0,1.5,false\n\
+             anonymized,7.5,0,7.5,false\n";
+    assert!(!csv_semantically_equal(e.as_bytes(), g.as_bytes()));
+}
+
+#[test]
+fn consume_parallel_matches_consume() {
+    // per client history of deposits, withdrawals and disputes, the clients
+    // interleaved pseudo randomly while keeping each client's order
+    let transactions = || {
+        let mut histories: Vec<Vec<Transaction>> = (0..40u16)
+            .map(|client_id| {
+                let base = u32::from(client_id) * 100;
+                let mut history = vec![
+                    Transaction::new(Op::Deposit, client_id, base, Some(dec!(10))),
+                    Transaction::new(Op::Deposit, client_id, base + 1, Some(dec!(5))),
+                    Transaction::new(Op::Withdrawal, client_id, base + 2, Some(dec!(3))),
+                    Transaction::new(Op::Dispute, client_id, base + 1, None),
+                ];
+                match client_id % 3 {
+                    0 => history.push(Transaction::new(Op::Resolve, client_id, base + 1, None)),
+                    1 => history.push(Transaction::new(Op::Chargeback, client_id, base + 1, None)),
+                    _ => {}
+                }
+                history.push(Transaction::new(
+                    Op::Withdrawal,
+                    client_id,
+                    base + 3,
+                    Some(dec!(20)),
+                ));
+                history.reverse();
+                history
+            })
+            .collect();
+
+        let mut transactions = Vec::new();
+        let mut state = 7u32;
+        while histories.iter().any(|history| !history.is_empty()) {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let index = (state >> 16) as usize % histories.len();
+            if let Some(tx) = histories[index].pop() {
+                transactions.push(tx);
+            }
+        }
+        transactions
+    };
+
+    let bursar = |reorder_window| {
+        Bursar::new()
+            .with_client_map(ClientMapKind::BTree)
+            .with_reject_tracking(true)
+            .with_audit_log(true)
+            .with_processing_order(true)
+            .with_reorder_window(reorder_window)
+    };
+    let rejects = |bursar: &Bursar| -> Vec<(u32, TxError)> {
+        bursar
+            .rejects()
+            .iter()
+            .map(|(tx, err)| (tx.tx_id, *err))
+            .collect()
+    };
+    // a transaction id used by two clients and a dispute of another client's deposit
+    let shared_ids = || {
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(Op::Deposit, 2, 1, Some(dec!(7))),
+            Transaction::new(Op::Dispute, 2, 1, None),
+            Transaction::new(Op::Deposit, 3, 2, Some(dec!(5))),
+            Transaction::new(Op::Dispute, 4, 2, None),
+        ]
+    };
+    for (reorder_window, shared) in [(0, false), (3, false), (0, true)] {
+        let input = || match shared {
+            true => shared_ids(),
+            false => transactions(),
+        };
+        let mut serial = bursar(reorder_window);
+        serial.consume(input().into_iter());
+        let mut parallel = bursar(reorder_window);
+        parallel.consume_parallel(input().into_iter(), 4);
+
+        let mut expected = Vec::new();
+        serial.write_results(&mut expected).unwrap();
+        let mut actual = Vec::new();
+        parallel.write_results(&mut actual).unwrap();
+        assert_eq!(
+            String::from_utf8(actual).unwrap(),
+            String::from_utf8(expected).unwrap()
+        );
+        assert_eq!(parallel.stats(), serial.stats());
+        assert_eq!(parallel.errors(), serial.errors());
+        if shared {
+            assert_eq!(
+                parallel.errors(),
+                [
+                    TxError::DuplicateTxId,
+                    TxError::ForeignTransaction,
+                    TxError::ForeignTransaction
+                ]
+            );
+        }
+        assert_eq!(rejects(&parallel), rejects(&serial));
+        assert_eq!(parallel.audit_log(), serial.audit_log());
+        assert_eq!(parallel.processed_order(), serial.processed_order());
+
+        // same clock as well as lock and record times
+        let mut expected = Vec::new();
+        serial.dump_debug(&mut expected).unwrap();
+        let mut actual = Vec::new();
+        parallel.dump_debug(&mut actual).unwrap();
+        assert_eq!(
+            String::from_utf8(actual).unwrap(),
+            String::from_utf8(expected).unwrap()
+        );
+    }
+}
+
+#[test]
+fn results_as_json() {
+    let mut bursar = Bursar::new().with_client_map(ClientMapKind::BTree);
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(1.5))),
+            Transaction::new(Op::Deposit, 2, 2, Some(dec!(2))),
+            Transaction::new(Op::Dispute, 2, 2, None),
+            Transaction::new(Op::Chargeback, 2, 2, None),
+        ]
+        .into_iter(),
+    );
+
+    let mut output = Vec::new();
+    bursar.write_results_json(&mut output).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!([
+            {"client": 1, "available": "1.5", "held": "0.0000", "total": "1.5", "locked": false},
+            {"client": 2, "available": "0.0000", "held": "0.0000", "total": "0.0000", "locked": true},
+        ])
+    );
+}
+
+#[test]
+fn registered_clients_required() {
+    let mut bursar = Bursar::new().require_registered_clients(true);
+    bursar.register_client(1);
+
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Deposit, 2, 1, Some(dec!(1)))),
+        Err(TxError::UnregisteredClient)
+    );
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Deposit, 1, 2, Some(dec!(1)))),
+        Ok(())
+    );
+
+    let mut output = Vec::new();
+    bursar.write_results(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n1,1,0.0000,1,false\n"
+    );
+}
+
+#[test]
+fn average_deposit() {
+    let mut bursar = Bursar::new();
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(Op::Deposit, 1, 2, Some(dec!(30))),
+            Transaction::new(Op::Withdrawal, 1, 3, Some(dec!(5))),
+            Transaction::new(Op::Dispute, 1, 1, None),
+            Transaction::new(Op::Withdrawal, 2, 4, Some(dec!(5))),
+        ]
+        .into_iter(),
+    );
+
+    assert_eq!(bursar.avg_deposit(1), Some(dec!(20)));
+    assert_eq!(bursar.avg_deposit(2), None);
+    assert_eq!(bursar.avg_deposit(3), None);
+}
+
+#[test]
+fn save_and_load_state() {
+    let transactions = || {
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(10.5))),
+            Transaction::new(Op::Deposit, 2, 2, Some(dec!(4))),
+            Transaction::new(Op::Deposit, 1, 3, Some(dec!(2.25))),
+            Transaction::new(Op::Dispute, 2, 2, None),
+            Transaction::new(Op::Withdrawal, 3, 5, Some(dec!(1))),
+            // second half, referencing transactions of the first
+            Transaction::new(Op::Dispute, 1, 1, None),
+            Transaction::new(Op::Resolve, 2, 2, None),
+            Transaction::new(Op::Chargeback, 1, 1, None),
+            Transaction::new(Op::Withdrawal, 2, 4, Some(dec!(1))),
+        ]
+    };
+    let mut expected = Vec::new();
+    let mut whole = Bursar::new().with_client_map(ClientMapKind::BTree);
+    whole.consume(transactions().into_iter());
+    whole.write_results(&mut expected).unwrap();
+
+    let mut first = Bursar::new();
+    first.consume(transactions().into_iter().take(5));
+    let mut state = Vec::new();
+    first.save_state(&mut state).unwrap();
+
+    let mut resumed = Bursar::load_state(state.as_slice())
+        .unwrap()
+        .with_client_map(ClientMapKind::BTree);
+    resumed.consume(transactions().into_iter().skip(5));
+    let mut actual = Vec::new();
+    resumed.write_results(&mut actual).unwrap();
+    assert_eq!(
+        String::from_utf8(actual).unwrap(),
+        String::from_utf8(expected).unwrap()
+    );
+    assert_eq!(resumed.stats(), whole.stats());
+    assert_eq!(resumed.summary(), whole.summary());
+    assert_eq!(resumed.summary().clients, 3);
+}
+
+#[test]
+fn withdrawals_cannot_be_disputed() {
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(4))));
+
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Dispute, 1, 2, None)),
+        Err(TxError::WithdrawalDispute)
+    );
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Chargeback, 1, 2, None)),
+        Err(TxError::NotDisputed)
+    );
+    assert_eq!(
+        bursar.dispute_batch(1, &[1, 2]),
+        Err(BatchDisputeError::Withdrawal(2))
+    );
+    assert_eq!(bursar.exact_balances(1), Some((dec!(6), dec!(0))));
+}
+
+#[test]
+fn shadow_rules_only_count() {
+    let max_amount = |tx: &Transaction| tx.amount().is_none_or(|amount| amount <= dec!(100));
+    let mut bursar = Bursar::new().with_rule("max_amount", RuleMode::Shadow, max_amount);
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(50))),
+            Transaction::new(Op::Deposit, 1, 2, Some(dec!(500))),
+            Transaction::new(Op::Withdrawal, 1, 3, Some(dec!(150))),
+        ]
+        .into_iter(),
+    );
+
+    let stats = bursar.stats();
+    assert_eq!(stats.shadow_rejected, 2);
+    assert_eq!(stats.rejected, 0);
+    assert_eq!(bursar.exact_balances(1), Some((dec!(400), dec!(0))));
+
+    let mut bursar = Bursar::new().with_rule("max_amount", RuleMode::Enforce, max_amount);
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Deposit, 1, 1, Some(dec!(500)))),
+        Err(TxError::RuleViolation)
+    );
+    assert_eq!(bursar.stats().shadow_rejected, 0);
+}
+
+#[test]
+fn tombstoned_client_is_anonymized() {
+    let mut bursar = Bursar::new().with_client_map(ClientMapKind::BTree);
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(3))),
+            Transaction::new(Op::Deposit, 2, 2, Some(dec!(7.5))),
+        ]
+        .into_iter(),
+    );
+    bursar.tombstone_client(2);
+
+    assert_eq!(bursar.exact_balances(2), None);
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Dispute, 2, 2, None)),
+        Err(TxError::UnknownTransaction)
+    );
+    let mut output = Vec::new();
+    bursar.write_results(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n\
+         1,3,0.0000,3,false\n\
+         anonymized,7.5,0.0000,7.5,false\n"
+    );
+    assert_eq!(bursar.stats().clients, 2);
+    assert_eq!(bursar.stats().available, dec!(10.5));
+
+    // every writer includes the tombstone
+    let mut output = Vec::new();
+    bursar.serialize_results(&mut output).unwrap();
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .ends_with("anonymized,7.5,0.0000,7.5,false\n"));
+    let mut output = Vec::new();
+    assert_eq!(bursar.write_results_best_effort(&mut output).unwrap(), 0);
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .ends_with("anonymized,7.5,0.0000,7.5,false\n"));
+    assert_eq!(
+        bursar.commitment_leaves().unwrap().last().unwrap(),
+        b"anonymized,7.5,0.0000,7.5,false\n"
+    );
+    let mut output = Vec::new();
+    bursar.write_results_incremental(&mut output).unwrap();
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .ends_with("anonymized,7.5,0.0000,7.5,false\n"));
+    let mut output = Vec::new();
+    bursar.write_results_incremental(&mut output).unwrap();
+    assert!(output.is_empty());
+
+    // a new client under the id is written on its own
+    bursar.process_transaction(Transaction::new(Op::Deposit, 2, 3, Some(dec!(1))));
+    let mut output = Vec::new();
+    bursar.write_results_incremental(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n2,1,0.0000,1,false\n"
+    );
+}
+
+#[test]
+fn spaced_csv_with_and_without_headers() {
+    let with_headers = "Type , Client, TX, Amount\n deposit, 1, 1, 20.0\nwithdrawal , 1, 2, 5.5 \n";
+    let headerless = "deposit, 1, 1, 20.0\nwithdrawal , 1, 2, 5.5 \n";
+    for data in [with_headers, headerless] {
+        let mut reader = transaction_reader(data.as_bytes(), &HashMap::new()).unwrap();
+        let transactions: Vec<Transaction> = read_transactions(&mut reader).collect();
+        assert_eq!(transactions.len(), 2);
+
+        // the header is recognized even when the source hands out a byte at a time
+        let mut reader = transaction_reader(Trickle(data.as_bytes()), &HashMap::new()).unwrap();
+        let trickled: Vec<Transaction> = read_transactions(&mut reader).collect();
+        assert_eq!(trickled.len(), 2);
+        assert_eq!(trickled[0].op(), &Op::Deposit);
+        assert_eq!(trickled[1].amount(), Some(dec!(5.5)));
+        assert_eq!(transactions[0].op(), &Op::Deposit);
+        assert_eq!(
+            (transactions[0].client_id(), transactions[0].tx_id()),
+            (1, 1)
+        );
+        assert_eq!(transactions[0].amount(), Some(dec!(20.0)));
+        assert_eq!(transactions[1].op(), &Op::Withdrawal);
+        assert_eq!(transactions[1].amount(), Some(dec!(5.5)));
+    }
+}
+
+#[test]
+fn run_summary() {
+    let mut bursar = Bursar::new();
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(Op::Deposit, 2, 2, Some(dec!(5))),
+            Transaction::new(Op::Deposit, 2, 2, Some(dec!(5))),
+            Transaction::new(Op::Withdrawal, 1, 3, Some(dec!(4))),
+            Transaction::new(Op::Withdrawal, 3, 4, Some(dec!(1))),
+            Transaction::new(Op::Dispute, 1, 1, None),
+            Transaction::new(Op::Resolve, 1, 1, None),
+            Transaction::new(Op::Resolve, 2, 2, None),
+            Transaction::new(Op::Dispute, 2, 2, None),
+            Transaction::new(Op::Chargeback, 2, 2, None),
+        ]
+        .into_iter(),
+    );
+
+    let summary = bursar.summary();
+    let counts = |applied, rejected| OpCounts { applied, rejected };
+    assert_eq!(summary.deposits, counts(2, 1));
+    assert_eq!(summary.withdrawals, counts(1, 1));
+    assert_eq!(summary.disputes, counts(2, 0));
+    assert_eq!(summary.resolves, counts(1, 1));
+    assert_eq!(summary.chargebacks, counts(1, 0));
+    assert_eq!(summary.flags, counts(0, 0));
+    assert_eq!(
+        summary.rejected_by_reason,
+        BTreeMap::from([
+            ("duplicate_tx_id", 1),
+            ("insufficient_funds", 1),
+            ("not_disputed", 1)
+        ])
+    );
+    assert_eq!(summary.clients, 3);
+    assert!(summary
+        .to_string()
+        .starts_with("deposits: 2 applied, 1 rejected\nwithdrawals: 1 applied, 1 rejected\n"));
+}
+
+#[test]
+fn stored_rounding() {
+    let run = |stored: bool| {
+        let mut bursar = Bursar::new()
+            .with_rounding(Rounding::HalfAwayFromZero)
+            .with_stored_rounding(stored);
+        bursar.consume(
+            vec![
+                Transaction::new(Op::Deposit, 1, 1, Some(dec!(0.0002))),
+                Transaction::new(Op::Deposit, 1, 2, Some(dec!(0.00005))),
+                Transaction::new(Op::Dispute, 1, 2, None),
+                Transaction::new(Op::Resolve, 1, 2, None),
+            ]
+            .into_iter(),
+        );
+        let mut output = Vec::new();
+        bursar.write_results(&mut output).unwrap();
+        (bursar, String::from_utf8(output).unwrap())
+    };
+
+    // rounded once on output the dispute cycle cancels out
+    let (bursar, output) = run(false);
+    assert_eq!(bursar.exact_balances(1), Some((dec!(0.00025), dec!(0))));
+    assert_eq!(bursar.verify_dispute_neutral(2), Ok(()));
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,0.0003,0.0000,0.0003,false\n"
+    );
+
+    // every step rounding half away from zero leaves a residual behind
+    let (bursar, output) = run(true);
+    assert_eq!(bursar.exact_balances(1), Some((dec!(0.0004), dec!(0.0001))));
+    assert_eq!(
+        bursar.verify_dispute_neutral(2),
+        Err(NotNeutral::Residual {
+            available: dec!(0.0001),
+            held: dec!(0.0001)
+        })
+    );
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,0.0004,0.0001,0.0005,false\n"
+    );
+}
+
+#[test]
+fn max_exposure() {
+    let mut bursar = Bursar::new();
+    assert_eq!(bursar.max_exposure_client(), None);
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 3, 1, Some(dec!(5))),
+            Transaction::new(Op::Deposit, 1, 2, Some(dec!(2))),
+            Transaction::new(Op::Deposit, 2, 3, Some(dec!(5))),
+            Transaction::new(Op::Dispute, 3, 1, None),
+            Transaction::new(Op::Dispute, 1, 2, None),
+            Transaction::new(Op::Dispute, 2, 3, None),
+        ]
+        .into_iter(),
+    );
+    assert_eq!(bursar.max_exposure_client(), Some((2, dec!(5))));
+
+    bursar.process_transaction(Transaction::new(Op::Resolve, 2, 3, None));
+    assert_eq!(bursar.max_exposure_client(), Some((3, dec!(5))));
+}
+
+#[test]
+fn tx_store_matches_hash_map() {
+    let transactions = || {
+        (0..50u32).flat_map(|tx_id| {
+            let client_id = (tx_id % 5) as u16;
+            let mut txs = vec![Transaction::new(
+                Op::Deposit,
+                client_id,
+                tx_id,
+                Some(dec!(3)),
+            )];
+            match tx_id % 7 {
+                0 => txs.push(Transaction::new(Op::Dispute, client_id, tx_id, None)),
+                3 if tx_id > 10 => {
+                    txs.push(Transaction::new(Op::Dispute, client_id, tx_id - 10, None));
+                    txs.push(Transaction::new(
+                        Op::Chargeback,
+                        client_id,
+                        tx_id - 10,
+                        None,
+                    ));
+                }
+                _ => txs.push(Transaction::new(
+                    Op::Withdrawal,
+                    client_id,
+                    tx_id + 1000,
+                    Some(dec!(1)),
+                )),
+            }
+            txs
+        })
+    };
+    let results = |bursar: Bursar| {
+        let mut bursar = bursar.with_client_map(ClientMapKind::BTree);
+        bursar.consume(transactions());
+        let mut output = Vec::new();
+        bursar.write_results(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    assert_eq!(
+        results(Bursar::new().with_tx_store(LruTxStore::new(1000))),
+        results(Bursar::new())
+    );
+}
+
+#[test]
+fn lru_tx_store_evicts() {
+    let record = |amount| TxRecord {
+        op: Op::Deposit,
+        client_id: 1,
+        amount,
+        seq: 0,
+    };
+    let mut store = LruTxStore::new(2);
+    store.insert(1, record(dec!(1)));
+    store.insert(2, record(dec!(2)));
+    assert_eq!(store.get(1), Some(record(dec!(1))));
+    store.insert(3, record(dec!(3)));
+    assert!(!store.contains(2));
+    assert_eq!(store.len(), 2);
+
+    let mut bursar = Bursar::new().with_tx_store(LruTxStore::new(2));
+    for tx_id in 1..=3 {
+        bursar.process_transaction(Transaction::new(Op::Deposit, 1, tx_id, Some(dec!(1))));
+    }
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Dispute, 1, 1, None)),
+        Err(TxError::UnknownTransaction)
+    );
+    assert_eq!(
+        bursar.try_process(Transaction::new(Op::Dispute, 1, 3, None)),
+        Ok(())
+    );
+    assert_eq!(bursar.exact_balances(1), Some((dec!(2), dec!(1))));
+}
+
+#[test]
+#[should_panic(expected = "capacity must be positive")]
+fn lru_tx_store_without_capacity() {
+    LruTxStore::new(0);
+}
+
+#[test]
+fn try_consume_stops_at_first_error() {
+    let mut bursar = Bursar::new();
+    let transactions = vec![
+        Ok(Transaction::new(Op::Deposit, 1, 1, Some(dec!(5)))),
+        Ok(Transaction::new(Op::Deposit, 1, 2, Some(dec!(3)))),
+        Err("malformed row"),
+        Ok(Transaction::new(Op::Deposit, 1, 3, Some(dec!(1)))),
+    ];
+
+    assert_eq!(
+        bursar.try_consume(transactions.into_iter()),
+        Err("malformed row")
+    );
+    assert_eq!(bursar.exact_balances(1), Some((dec!(8), dec!(0))));
+    assert_eq!(bursar.stats().processed, 2);
+
+    let mut reader = transaction_reader(
+        "type,client,tx,amount\ndeposit,1,1,2\n".as_bytes(),
+        &HashMap::new(),
+    )
+    .unwrap();
+    let mut bursar = Bursar::new();
+    bursar.try_consume(reader.deserialize()).unwrap();
+    assert_eq!(bursar.exact_balances(1), Some((dec!(2), dec!(0))));
+}
+
+#[test]
+fn saved_state_keeps_dispute_owner() {
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Deposit, 2, 2, Some(dec!(4))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 1, 1, None));
+    let mut state = Vec::new();
+    bursar.save_state(&mut state).unwrap();
+
+    let mut restored = Bursar::load_state(state.as_slice()).unwrap();
+    assert_eq!(restored.exact_balances(1), Some((dec!(0), dec!(10))));
+    assert_eq!(
+        restored.try_process(Transaction::new(Op::Resolve, 2, 1, None)),
+        Err(TxError::ForeignTransaction)
+    );
+    assert_eq!(
+        restored.try_process(Transaction::new(Op::Resolve, 1, 1, None)),
+        Ok(())
+    );
+    assert_eq!(restored.exact_balances(1), Some((dec!(10), dec!(0))));
+    assert_eq!(restored.verify_invariants(), Ok(()));
+}
+
+#[test]
+fn precision_loss_rejected() {
+    let mut bursar = Bursar::new().reject_on_precision_loss(true);
+    let data =
+        "type,client,tx,amount\ndeposit,1,1,1.23456\ndeposit,1,2,2.50000\nwithdrawal,1,3,0.00001\n";
+    let mut reader = transaction_reader(data.as_bytes(), &HashMap::new()).unwrap();
+    bursar.consume(read_transactions(&mut reader));
+
+    assert_eq!(
+        bursar.errors(),
+        [TxError::PrecisionLoss, TxError::PrecisionLoss]
+    );
+    assert_eq!(bursar.exact_balances(1), Some((dec!(2.5), dec!(0))));
+
+    let mut bursar = Bursar::new()
+        .with_precision(6)
+        .reject_on_precision_loss(true);
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(1.23456))));
+    assert!(bursar.errors().is_empty());
+}
+
+#[test]
+fn release_exceeding_held_rejected() {
+    let mut bursar = Bursar::new();
+    bursar.process_transaction(Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))));
+    bursar.process_transaction(Transaction::new(Op::Dispute, 1, 1, None));
+    // held shrunk behind the dispute's back
+    bursar.clients.get_mut(&1).unwrap().held = dec!(4);
+
+    for op in [Op::Resolve, Op::Chargeback] {
+        assert_eq!(
+            bursar.try_process(Transaction::new(op, 1, 1, None)),
+            Err(TxError::InsufficientHeld)
+        );
+    }
+    assert_eq!(bursar.exact_balances(1), Some((dec!(0), dec!(4))));
+    assert!(bursar.disputed.contains_key(&1));
+    assert!(!bursar.client_balance(1).unwrap().locked);
+}
+
+#[test]
+fn reconciliation_report() {
+    let mut bursar = Bursar::new();
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(3))),
+            Transaction::new(Op::Dispute, 1, 1, None),
+            Transaction::new(Op::Deposit, 2, 3, Some(dec!(5))),
+        ]
+        .into_iter(),
+    );
+    // funds appearing out of nowhere
+    bursar.clients.get_mut(&2).unwrap().available = dec!(6);
+
+    let mut output = Vec::new();
+    bursar.write_reconciliation(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,net_flow,held,available,mismatch\n1,7,10,-3,false\n2,5,0,6,true\n"
+    );
+}
+
+#[test]
+fn pause_and_resume_keep_reorder_buffer() {
+    let mut bursar = Bursar::new().with_reorder_window(5);
+    bursar.feed(
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(Op::Resolve, 1, 1, None),
+        ]
+        .into_iter(),
+    );
+    let paused = bursar.pause();
+    assert_eq!(paused.buffered(), 1);
+    let mut state = Vec::new();
+    bursar.save_state(&mut state).unwrap();
+    let paused = serde_json::to_vec(&paused).unwrap();
+
+    let mut resumed = Bursar::load_state(state.as_slice())
+        .unwrap()
+        .with_reorder_window(5);
+    resumed.resume(serde_json::from_slice(&paused).unwrap());
+    resumed.feed(vec![Transaction::new(Op::Dispute, 1, 1, None)].into_iter());
+    resumed.finish();
+
+    assert!(resumed.errors().is_empty());
+    assert_eq!(resumed.exact_balances(1), Some((dec!(10), dec!(0))));
+    assert!(resumed.disputed.is_empty());
+    assert_eq!(resumed.clients.get(&1).unwrap().resolves, 1);
+}
+
+#[test]
+fn negative_available_is_remembered() {
+    capture_logs();
+    let mut bursar = Bursar::new();
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(Op::Withdrawal, 1, 2, Some(dec!(8))),
+            Transaction::new(Op::Dispute, 1, 1, None),
+            Transaction::new(Op::Deposit, 1, 3, Some(dec!(20))),
+            Transaction::new(Op::Deposit, 2, 4, Some(dec!(1))),
+        ]
+        .into_iter(),
+    );
+
+    let snapshots: Vec<ClientSnapshot> = bursar.clients().collect();
+    let client = snapshots
+        .iter()
+        .find(|client| client.client_id == 1)
+        .unwrap();
+    assert!(client.went_negative);
+    assert_eq!(client.available, dec!(12));
+    let other = snapshots
+        .iter()
+        .find(|client| client.client_id == 2)
+        .unwrap();
+    assert!(!other.went_negative);
+    assert!(captured_logs()
+        .iter()
+        .any(|line| line == "WARN available of client '1' went negative: -8"));
+}
+
+#[test]
+fn fixed_width_results() {
+    let mut bursar = Bursar::new().with_client_map(ClientMapKind::BTree);
+    bursar.consume(
+        vec![
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(12.5))),
+            Transaction::new(Op::Deposit, 22, 2, Some(dec!(3))),
+            Transaction::new(Op::Dispute, 22, 2, None),
+            Transaction::new(Op::Chargeback, 22, 2, None),
+        ]
+        .into_iter(),
+    );
+    let mut widths = FieldWidths {
+        client: 5,
+        available: 8,
+        held: 8,
+        total: 8,
+        locked: 5,
+        zero_pad: false,
+    };
+
+    let mut output = Vec::new();
+    bursar
+        .write_results_fixed_width(&mut output, widths)
+        .unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(
+        output,
+        "1    12.5    0.0000  12.5    false\n\
+         22   0.0000  0.0000  0.0000  true \n"
+    );
+    assert!(output.lines().all(|line| line.len() == 34));
+
+    widths.zero_pad = true;
+    let mut output = Vec::new();
+    bursar
+        .write_results_fixed_width(&mut output, widths)
+        .unwrap();
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .starts_with("00001000012.5000.0000000012.5false\n"));
+
+    widths.available = 3;
+    let err = bursar
+        .write_results_fixed_width(io::sink(), widths)
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(
+        err.to_string(),
+        "available '12.5' is 4 characters wide, exceeding its width of 3"
+    );
+
+    bursar.tombstone_client(1);
+    widths.client = 12;
+    widths.available = 8;
+    let mut output = Vec::new();
+    bursar
+        .write_results_fixed_width(&mut output, widths)
+        .unwrap();
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .ends_with("\nanonymized  000012.5000.0000000012.5false\n"));
+}
+
+#[test]
+fn consume_ordered_breaks_ties_by_input_order() {
+    let mut bursar = Bursar::new().with_reject_tracking(true);
+    bursar.consume_ordered(
+        vec![
+            Transaction::new(Op::Dispute, 1, 1, None).with_timestamp(20),
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(5))).with_timestamp(10),
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(7))).with_timestamp(10),
+            Transaction::new(Op::Deposit, 1, 1, Some(dec!(9))).with_timestamp(10),
+        ]
+        .into_iter(),
+    );
+
+    // the first of the tied deposits is applied, the later ones are duplicates
+    assert_eq!(bursar.exact_balances(1), Some((dec!(0), dec!(5))));
+    let rejected: Vec<Option<Decimal>> = bursar
+        .rejects()
+        .iter()
+        .map(|(tx, err)| {
+            assert_eq!(*err, TxError::DuplicateTxId);
+            tx.amount()
+        })
+        .collect();
+    assert_eq!(rejected, [Some(dec!(7)), Some(dec!(9))]);
 }
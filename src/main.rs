@@ -1,33 +1,56 @@
+use bursar::{Bursar, BursarConfig};
 use log::error;
-use std::{env, io};
-
-mod bursar;
-use crate::bursar::{Bursar, Transaction};
+use std::{env, fs, io};
 
 fn main() {
     env_logger::init();
-    let args: Vec<String> = env::args().collect();
+    let mut args = env::args().skip(1);
+    let mut config = BursarConfig::default();
+    let mut input = None;
+    let mut summary = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => {
+                let path = args.next().unwrap_or_else(|| {
+                    error!("--config requires a file path");
+                    std::process::exit(1);
+                });
+                config = BursarConfig::from_file(&path).unwrap_or_else(|err| {
+                    error!("Could not load config file: {}", err);
+                    std::process::exit(1);
+                });
+            }
+            // report the processed transactions on stderr at the end
+            "--summary" => summary = true,
+            _ if input.is_none() => input = Some(arg),
+            _ => {
+                error!("Exactly one input file is supported");
+                std::process::exit(1);
+            }
+        }
+    }
+    // no path or `-` reads the transactions from stdin
+    let source: Box<dyn io::Read> = match input.as_deref() {
+        None | Some("-") => Box::new(io::stdin().lock()),
+        Some(path) => {
+            let file_path = std::path::Path::new(path);
+            if !file_path.exists() {
+                error!("File path does not exist");
+                std::process::exit(1);
+            }
+            Box::new(fs::File::open(file_path).expect("Could not open csv file"))
+        }
+    };
 
-    if args.len() != 2 {
-        error!("Exactly one argument is supported");
+    let mut bursar = Bursar::with_config(config);
+    bursar
+        .consume_reader(source)
+        .expect("Could not read csv file");
+    if let Err(err) = bursar.write_results(io::stdout()) {
+        error!("Unable to write results: {}", err);
         std::process::exit(1);
     }
-    let file_path = std::path::Path::new(&args[1]);
-    if !file_path.exists() {
-        error!("File path does not exist");
-        std::process::exit(1);
+    if summary {
+        eprintln!("{}", bursar.summary());
     }
-
-    let mut reader = csv::Reader::from_path(file_path).expect("Could not read csv file");
-
-    let tx_iter = reader.deserialize::<Transaction>().filter_map(|item| {
-        if item.is_err() {
-            error!("could not parse transaction, will be skipped: {:?}", item)
-        }
-        item.ok()
-    });
-
-    let mut bursar = Bursar::new();
-    bursar.consume(tx_iter);
-    bursar.write_results(io::stdout());
 }
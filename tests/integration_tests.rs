@@ -13,7 +13,7 @@ fn sanity() {
     bursar.consume(transactions.into_iter());
 
     let mut output = Vec::new();
-    bursar.write_results(&mut output);
+    bursar.write_results(&mut output).unwrap();
 
     assert_eq!(output, b"client,available,held,total,locked\n1,0.0000,0.0000,0.0000,false\n");
 }